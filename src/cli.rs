@@ -17,9 +17,76 @@ pub fn build_cli() -> clap::App<'static, 'static> {
                 .takes_value(true)
                 .help("State directory"),
         )
+        .arg(
+            Arg::with_name("audio_format")
+                .long("audio-format")
+                .takes_value(true)
+                .possible_values(&["mp3", "flac"])
+                .default_value("mp3")
+                .help("Audio format to download"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("NAME")
+                .help(
+                    "Video profile, built-in ones are legacy, youtube-mp4, youtube-blur, \
+                     youtube-waveform and mkv-flac, more can be defined in profiles.json \
+                     in the state directory [default: legacy]",
+                ),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of tracks to convert at once [default: 1]"),
+        )
+        .arg(
+            Arg::with_name("album_video")
+                .long("album-video")
+                .takes_value(true)
+                .value_name("COVERS")
+                .possible_values(&["single", "per-track"])
+                .help(
+                    "Also upload the whole album as one video with chapters, \
+                     with the album cover or the cover of each track",
+                ),
+        )
+        .arg(
+            Arg::with_name("audio_codec")
+                .long("audio-codec")
+                .takes_value(true)
+                .possible_values(&["copy", "flac", "aac", "opus"])
+                .help("How to encode audio in the videos, overrides the profile"),
+        )
+        .arg(
+            Arg::with_name("audio_bitrate")
+                .long("audio-bitrate")
+                .takes_value(true)
+                .value_name("KBPS")
+                .help("Bitrate for the aac and opus audio codecs"),
+        )
+        .arg(
+            Arg::with_name("reject_license")
+                .long("reject-license")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KIND")
+                .possible_values(&["non-cc", "nc", "nd", "sa", "none"])
+                .help("Don't upload albums with such license, can be repeated [default: non-cc]"),
+        )
+        .arg(
+            Arg::with_name("fake_youtube")
+                .long("fake-youtube")
+                .help("Don't talk to YouTube, record uploads in the state directory instead"),
+        )
         .subcommand(
             App::new("scrape-ektoplazm")
-                .about("get the list of all albums from ektoplazm.com")
+                .about("get the list of albums from ektoplazm.com")
                 .setting(clap::AppSettings::DisableVersion)
                 .arg(
                     Arg::with_name("offset")
@@ -29,10 +96,71 @@ pub fn build_cli() -> clap::App<'static, 'static> {
                         .help("Skip N albums (will be rounded)")
                         .default_value("0"),
                 )
+                .arg(
+                    Arg::with_name("listing")
+                        .long("listing")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("URL")
+                        .help("Listing page to scrape instead of the free music section, can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("style")
+                        .long("style")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME")
+                        .help("Scrape albums of this style, e.g. \"Psy Dub\", can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("label")
+                        .long("label")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME")
+                        .help("Scrape albums released by this label, can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("artist")
+                        .long("artist")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME")
+                        .help("Scrape albums of this artist, can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("new_only")
+                        .long("new-only")
+                        .conflicts_with("offset")
+                        .help("Start from the newest albums, stop when reaching known ones"),
+                )
+                .arg(
+                    Arg::with_name("stop_after")
+                        .long("stop-after")
+                        .takes_value(true)
+                        .value_name("N")
+                        .requires("new_only")
+                        .help("Stop after N consecutive known albums [default: 10]"),
+                )
                 .arg(
                     Arg::with_name("really")
                         .long("really")
                         .help("Really do a lot of HTTP requests?")
+                        .required_unless("new_only"),
+                ),
+        )
+        .subcommand(
+            App::new("scrape-archiveorg")
+                .about("get the list of all items in archive.org collection")
+                .setting(clap::AppSettings::DisableVersion)
+                .arg(
+                    Arg::with_name("collection")
+                        .help("Collection identifier, e.g. enoughrecords")
+                        .index(1)
                         .required(true),
                 ),
         )
@@ -96,10 +224,11 @@ pub fn build_cli() -> clap::App<'static, 'static> {
                 .setting(clap::AppSettings::DisableVersion)
                 .arg(
                     Arg::with_name("url")
-                        .help("URL of the album")
+                        .help("URL of the album, or path to local directory or ZIP file")
                         .index(1)
                         .required(true),
-                ),
+                )
+                .args(&album_override_args()),
         )
         .subcommand(
             App::new("video")
@@ -129,12 +258,31 @@ pub fn build_cli() -> clap::App<'static, 'static> {
             App::new("url")
                 .about("process source URL - download, convert to videos, upload to youtube")
                 .setting(clap::AppSettings::DisableVersion)
-                .arg(Arg::with_name("url").index(1).required(true)),
+                .arg(
+                    Arg::with_name("url")
+                        .help("URL of the album, or path to local directory or ZIP file")
+                        .index(1)
+                        .required(true),
+                )
+                .args(&album_override_args()),
         )
         .subcommand(
             App::new("daemon")
                 .about("process URLs from database")
-                .setting(clap::AppSettings::DisableVersion),
+                .setting(clap::AppSettings::DisableVersion)
+                .arg(
+                    Arg::with_name("once")
+                        .long("once")
+                        .help("Exit when there is nothing left to do"),
+                )
+                .arg(
+                    Arg::with_name("poll_interval")
+                        .long("poll-interval")
+                        .takes_value(true)
+                        .value_name("SECS")
+                        .conflicts_with("once")
+                        .help("How long to wait for new work [default: 600]"),
+                ),
         )
         .subcommand(
             App::new("status")
@@ -142,4 +290,185 @@ pub fn build_cli() -> clap::App<'static, 'static> {
                 .setting(clap::AppSettings::DisableVersion)
                 .arg(Arg::with_name("url").index(1).required(true)),
         )
+        .subcommand(
+            App::new("preview")
+                .about("render YouTube titles and descriptions for stored album")
+                .setting(clap::AppSettings::DisableVersion)
+                .arg(
+                    Arg::with_name("track")
+                        .long("track")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("Only the playlist and track number N"),
+                )
+                .arg(Arg::with_name("url").index(1).required(true)),
+        )
+        .subcommand(
+            App::new("queue")
+                .about("manage queued URLs")
+                .setting(clap::AppSettings::DisableVersion)
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("add")
+                        .about("queue URLs for processing")
+                        .arg(
+                            Arg::with_name("priority")
+                                .long("priority")
+                                .takes_value(true)
+                                .value_name("N")
+                                .allow_hyphen_values(true)
+                                .help("Higher priority URLs are processed first [default: 0]"),
+                        )
+                        .arg(
+                            Arg::with_name("url")
+                                .index(1)
+                                .multiple(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("list queued URLs in processing order")
+                        .arg(
+                            Arg::with_name("state")
+                                .long("state")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .possible_values(&QUEUE_STATES)
+                                .help("Only URLs in this state, can be repeated"),
+                        ),
+                )
+                .subcommand(
+                    App::new("retry")
+                        .about("process URLs again")
+                        .arg(
+                            Arg::with_name("failed")
+                                .long("failed")
+                                .help("Retry all failed URLs"),
+                        )
+                        .arg(
+                            Arg::with_name("license")
+                                .long("license")
+                                .conflicts_with("failed")
+                                .help("Retry URLs skipped by the license policy, e.g. after changing it"),
+                        )
+                        .arg(
+                            Arg::with_name("url")
+                                .index(1)
+                                .multiple(true)
+                                .required_unless_one(&["failed", "license"])
+                                .conflicts_with_all(&["failed", "license"]),
+                        ),
+                )
+                .subcommand(
+                    App::new("remove").about("remove URLs from queue").arg(
+                        Arg::with_name("url")
+                            .index(1)
+                            .multiple(true)
+                            .required(true),
+                    ),
+                )
+                .subcommand(
+                    App::new("bump")
+                        .about("process URLs before anything else")
+                        .arg(
+                            Arg::with_name("url")
+                                .index(1)
+                                .multiple(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(App::new("stats").about("count queued URLs by state")),
+        )
+        .subcommand(
+            App::new("blacklist")
+                .about("manage rules for albums that are never uploaded")
+                .setting(clap::AppSettings::DisableVersion)
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("add")
+                        .about("add rule")
+                        .args(&blacklist_rule_args()),
+                )
+                .subcommand(
+                    App::new("remove")
+                        .about("remove rule")
+                        .args(&blacklist_rule_args()),
+                )
+                .subcommand(App::new("list").about("list rules"))
+                .subcommand(
+                    App::new("test")
+                        .about("explain whether a stored album is blacklisted")
+                        .arg(Arg::with_name("url").index(1).required(true)),
+                ),
+        )
+        .subcommand(
+            App::new("history")
+                .about("show YouTube uploads")
+                .setting(clap::AppSettings::DisableVersion)
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .help("Uploads started on or after DATE (YYYY-MM-DD or RFC 3339)"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .help("Uploads started before DATE, whole day if no time is given"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .help("Only uploads of this album")
+                        .index(1),
+                ),
+        )
+}
+
+const QUEUE_STATES: [&str; 8] = [
+    "pending",
+    "in-progress",
+    "done",
+    "failed-permanent",
+    "failed-retryable",
+    "skipped-blacklist",
+    "skipped-no-license",
+    "skipped-license",
+];
+
+fn blacklist_rule_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("allow")
+            .long("allow")
+            .help("Exception to the other rules rather than a blacklist entry"),
+        Arg::with_name("type")
+            .index(1)
+            .required(true)
+            .possible_values(&["artist", "label", "tag", "album-title", "url"]),
+        Arg::with_name("pattern")
+            .index(2)
+            .required(true)
+            .help("Case-insensitive regular expression matching the whole value"),
+    ]
+}
+
+fn album_override_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("license")
+            .long("license")
+            .takes_value(true)
+            .value_name("URL")
+            .help("License of the album, overrides what the source says"),
+        Arg::with_name("label")
+            .long("label")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME")
+            .help("Label that released the album, can be repeated"),
+    ]
 }
@@ -2,31 +2,107 @@ use std::path::PathBuf;
 
 use crate::cli;
 use crate::flow;
+use crate::license;
+use crate::model;
 use crate::source;
 use crate::store;
+use crate::template::TemplateKind;
 use crate::util;
 use crate::video;
 use crate::youtube;
 
 pub enum Action {
     Help,
-    Scrape(u32),
+    Scrape {
+        listings: Vec<String>,
+        offset: u32,
+        stop_after_known: Option<u32>, // --new-only
+    },
+    ScrapeArchiveOrg(String),
     YTUpload(youtube::Video),
     YTPlaylist(youtube::Playlist),
-    Fetch(String),
+    Fetch(String, Overrides),
     Video {
         input: PathBuf,
         image: PathBuf,
         output: PathBuf,
     },
-    URL(String),
-    Daemon,
+    URL(String, Overrides),
+    Daemon {
+        poll: Option<std::time::Duration>, // None exits once the queue is drained
+    },
     Status(String),
+    Preview {
+        url: String,
+        track: Option<usize>, // 1-based
+    },
+    History {
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+        url: Option<String>,
+    },
+    Queue(QueueAction),
+    Blacklist(BlacklistAction),
+}
+
+pub enum QueueAction {
+    Add { urls: Vec<String>, priority: i32 },
+    List(Vec<store::QueueState>),
+    Retry(Vec<String>), // empty means all failed
+    RetryLicense,
+    Remove(Vec<String>),
+    Bump(Vec<String>),
+    Stats,
+}
+
+pub enum BlacklistAction {
+    Add(model::BlacklistRule),
+    Remove(model::BlacklistRule),
+    List,
+    Test(String),
+}
+
+// Album metadata given on the command line, takes precedence over what the source provides.
+#[derive(Default)]
+pub struct Overrides {
+    pub license: Option<String>,
+    pub labels: Vec<String>,
+}
+
+impl Overrides {
+    fn from_matches(matches: &clap::ArgMatches) -> Overrides {
+        Overrides {
+            license: matches.value_of("license").map(String::from),
+            labels: matches
+                .values_of("label")
+                .map(|v| v.map(String::from).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.license.is_none() && self.labels.is_empty()
+    }
+
+    fn apply(&self, album: &mut model::Album) {
+        if let Some(l) = &self.license {
+            album.license = Some(l.clone());
+        }
+        if !self.labels.is_empty() {
+            album.labels = self.labels.clone();
+        }
+    }
 }
 
 pub struct Config {
     pub verbose: usize,
     pub appdir: PathBuf,
+    pub fake_youtube: bool,
+    pub audio_format: model::AudioFormat,
+    pub license_policy: license::Policy,
+    pub profile: video::Profile,
+    pub album_video: Option<video::AlbumCovers>, // None: tracks and playlist only
+    pub jobs: usize,                             // ffmpeg processes at once
     pub action: Action,
 }
 
@@ -41,6 +117,50 @@ impl Config {
         if matches.is_present("statedir") {
             config.appdir = PathBuf::from(matches.value_of("statedir").unwrap());
         }
+        config.fake_youtube = matches.is_present("fake_youtube");
+        config.audio_format = match matches.value_of("audio_format").unwrap() {
+            "flac" => model::AudioFormat::Flac,
+            _ => model::AudioFormat::Mp3,
+        };
+        config.album_video = matches
+            .value_of("album_video")
+            .map(|c| video::AlbumCovers::from_name(c).expect("album video covers"));
+        if let Some(jobs) = matches.value_of("jobs") {
+            config.jobs = jobs.parse().expect("unsigned integer");
+        }
+        if let Some(name) = matches.value_of("profile") {
+            config.profile =
+                video::Profile::load(&config.profiles_file(), name).expect("video profile");
+        }
+        let bitrate = matches
+            .value_of("audio_bitrate")
+            .map(|b| b.parse().expect("unsigned integer"));
+        match (
+            matches.value_of("audio_codec"),
+            bitrate,
+            &config.profile.audio_codec,
+        ) {
+            (Some(codec), _, _) => {
+                config.profile.audio_codec =
+                    video::AudioCodec::from_name(codec, bitrate).expect("valid audio codec");
+            }
+            (None, Some(b), video::AudioCodec::Aac(_)) => {
+                config.profile.audio_codec = video::AudioCodec::Aac(b);
+            }
+            (None, Some(b), video::AudioCodec::Opus(_)) => {
+                config.profile.audio_codec = video::AudioCodec::Opus(b);
+            }
+            _ => {}
+        }
+
+        if let Some(kinds) = matches.values_of("reject_license") {
+            config.license_policy = license::Policy {
+                reject: kinds
+                    .filter(|k| *k != "none")
+                    .map(|k| license::Restriction::from_name(k).expect("license kind"))
+                    .collect(),
+            };
+        }
 
         if let Some(ref scrape_matches) = matches.subcommand_matches("scrape-ektoplazm") {
             let off: u32 = scrape_matches
@@ -48,7 +168,37 @@ impl Config {
                 .unwrap()
                 .parse()
                 .expect("unsigned integer");
-            config.action = Action::Scrape(off);
+            let stop: u32 = scrape_matches
+                .value_of("stop_after")
+                .unwrap_or("10")
+                .parse()
+                .expect("unsigned integer");
+            let mut listings: Vec<String> = scrape_matches
+                .values_of("listing")
+                .map(|v| v.map(String::from).collect())
+                .unwrap_or_default();
+            for kind in &["style", "label", "artist"] {
+                if let Some(names) = scrape_matches.values_of(kind) {
+                    listings.extend(names.map(|n| source::ektoplazm_listing(kind, n)));
+                }
+            }
+            if listings.is_empty() {
+                listings.push(source::EKTOPLAZM_FREE_MUSIC.to_string());
+            }
+            config.action = Action::Scrape {
+                listings,
+                offset: off,
+                stop_after_known: if scrape_matches.is_present("new_only") {
+                    Some(stop)
+                } else {
+                    None
+                },
+            };
+        }
+        if let Some(scrape_matches) = matches.subcommand_matches("scrape-archiveorg") {
+            config.action = Action::ScrapeArchiveOrg(
+                scrape_matches.value_of("collection").unwrap().to_string(),
+            );
         }
         if let Some(ref youtube_matches) = matches.subcommand_matches("yt-upload") {
             config.action = Action::YTUpload(youtube::Video {
@@ -77,7 +227,10 @@ impl Config {
             });
         }
         if let Some(ref fetch_matches) = matches.subcommand_matches("fetch") {
-            config.action = Action::Fetch(fetch_matches.value_of("url").unwrap().to_string());
+            config.action = Action::Fetch(
+                source::path_to_url(fetch_matches.value_of("url").unwrap()),
+                Overrides::from_matches(fetch_matches),
+            );
         }
         if let Some(ref video_matches) = matches.subcommand_matches("video") {
             let infile = PathBuf::from(video_matches.value_of("audio_file").unwrap());
@@ -86,7 +239,7 @@ impl Config {
                 None => {
                     let mut p = PathBuf::from(std::env::current_dir().unwrap());
                     p.push(infile.file_name().expect("audio file name"));
-                    p.set_extension("avi");
+                    p.set_extension(config.profile.extension(&infile));
                     p
                 }
             };
@@ -96,14 +249,91 @@ impl Config {
                 image: PathBuf::from(video_matches.value_of("image_file").unwrap()),
             };
         }
+        if let Some(queue_matches) = matches.subcommand_matches("queue") {
+            let urls = |m: &clap::ArgMatches| -> Vec<String> {
+                m.values_of("url")
+                    .map(|v| v.map(source::path_to_url).collect())
+                    .unwrap_or_default()
+            };
+            config.action = Action::Queue(match queue_matches.subcommand() {
+                ("add", Some(m)) => QueueAction::Add {
+                    urls: urls(m),
+                    priority: m
+                        .value_of("priority")
+                        .map_or(0, |p| p.parse().expect("priority")),
+                },
+                ("list", Some(m)) => QueueAction::List(
+                    m.values_of("state")
+                        .map(|v| {
+                            v.map(|s| store::QueueState::from_name(s).expect("queue state"))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                ),
+                ("retry", Some(m)) if m.is_present("license") => QueueAction::RetryLicense,
+                ("retry", Some(m)) => QueueAction::Retry(urls(m)),
+                ("remove", Some(m)) => QueueAction::Remove(urls(m)),
+                ("bump", Some(m)) => QueueAction::Bump(urls(m)),
+                ("stats", Some(_)) => QueueAction::Stats,
+                _ => unreachable!(),
+            });
+        }
+        if let Some(blacklist_matches) = matches.subcommand_matches("blacklist") {
+            let rule = |m: &clap::ArgMatches| model::BlacklistRule {
+                field: model::BlacklistField::from_name(m.value_of("type").unwrap())
+                    .expect("rule type"),
+                pattern: m.value_of("pattern").unwrap().to_string(),
+                allow: m.is_present("allow"),
+            };
+            config.action = Action::Blacklist(match blacklist_matches.subcommand() {
+                ("add", Some(m)) => BlacklistAction::Add(rule(m)),
+                ("remove", Some(m)) => BlacklistAction::Remove(rule(m)),
+                ("list", Some(_)) => BlacklistAction::List,
+                ("test", Some(m)) => {
+                    BlacklistAction::Test(source::path_to_url(m.value_of("url").unwrap()))
+                }
+                _ => unreachable!(),
+            });
+        }
+        if let Some(history_matches) = matches.subcommand_matches("history") {
+            config.action = Action::History {
+                since: history_matches
+                    .value_of("since")
+                    .map(|d| parse_time(d, false).expect("date")),
+                until: history_matches
+                    .value_of("until")
+                    .map(|d| parse_time(d, true).expect("date")),
+                url: history_matches.value_of("url").map(source::path_to_url),
+            };
+        }
         if let Some(ref url_matches) = matches.subcommand_matches("url") {
-            config.action = Action::URL(url_matches.value_of("url").unwrap().to_string());
+            config.action = Action::URL(
+                source::path_to_url(url_matches.value_of("url").unwrap()),
+                Overrides::from_matches(url_matches),
+            );
         }
-        if let Some(_) = matches.subcommand_matches("daemon") {
-            config.action = Action::Daemon;
+        if let Some(daemon_matches) = matches.subcommand_matches("daemon") {
+            let poll = if daemon_matches.is_present("once") {
+                None
+            } else {
+                let secs = daemon_matches
+                    .value_of("poll_interval")
+                    .map_or(600, |s| s.parse().expect("poll interval"));
+                Some(std::time::Duration::from_secs(secs))
+            };
+            config.action = Action::Daemon { poll };
+        }
+        if let Some(preview_matches) = matches.subcommand_matches("preview") {
+            config.action = Action::Preview {
+                url: source::path_to_url(preview_matches.value_of("url").unwrap()),
+                track: preview_matches
+                    .value_of("track")
+                    .map(|n| n.parse().expect("track number")),
+            };
         }
         if let Some(ref status_matches) = matches.subcommand_matches("status") {
-            config.action = Action::Status(status_matches.value_of("url").unwrap().to_string());
+            config.action =
+                Action::Status(source::path_to_url(status_matches.value_of("url").unwrap()));
         }
 
         config
@@ -138,12 +368,27 @@ impl Config {
         dir
     }
 
+    pub fn templates_dir(&self) -> PathBuf {
+        self.filename("templates")
+    }
+
+    pub fn profiles_file(&self) -> PathBuf {
+        self.filename("profiles.json")
+    }
+
+    pub fn fake_youtube_dir(&self) -> PathBuf {
+        self.filename("fake_youtube")
+    }
+
     // TODO: maybe make this lazy?
-    fn yt(&self) -> util::Result<youtube::YT> {
-        youtube::YT::new(
+    fn yt(&self) -> util::Result<Box<dyn youtube::Uploader>> {
+        if self.fake_youtube {
+            return Ok(Box::new(youtube::FakeYT::new(&self.fake_youtube_dir())));
+        }
+        Ok(Box::new(youtube::YT::new(
             self.client_secret().as_path(),
             self.filename("youtube_token.json").as_path(),
-        )
+        )?))
     }
 
     fn store(&self) -> util::Result<store::Store> {
@@ -157,58 +402,311 @@ impl Config {
                     "You have to specify an action, use --help for help",
                 ));
             }
-            Action::Scrape(off) => {
-                for (i, x) in source::EktoplazmScraper::from_offset(*off).enumerate() {
-                    // let (url, tracks, zipbytes) = x?;
-                    // println!("{} {}\t{}\t{}", (i as u32)+off, tracks, zipbytes, url);
+            Action::Scrape {
+                listings,
+                offset,
+                stop_after_known,
+            } => {
+                let mut store = self.store()?;
+                for listing in listings {
+                    log::info!("Scraping {}", listing);
+                    flow::scrape_ektoplazm(&mut store, listing, *offset, *stop_after_known)?;
+                }
+            }
+            Action::ScrapeArchiveOrg(collection) => {
+                for (i, x) in source::ArchiveOrgScraper::new(collection).enumerate() {
                     let url = x?;
-                    println!("{} {}", (i as u32) + off, url);
-                    self.store()?.queue_insert(&url)?;
-                    // doesn't make much sense now that we don't query all urls
-                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                    println!("{} {}", i, url);
+                    self.store()?.queue_insert(&url, 0)?;
                 }
             }
             Action::YTUpload(video) => {
-                println!("{}", self.yt()?.upload_video(video.clone())?.as_url());
+                let mut video = video.clone();
+                for note in video.sanitize() {
+                    log::warn!("{}", note);
+                }
+                println!("{}", self.yt()?.upload_video(video)?.as_url());
             }
             Action::YTPlaylist(playlist) => {
                 let yt = self.yt()?;
-                let playlist_id = yt.create_playlist(playlist.clone())?;
+                let mut playlist = playlist.clone();
+                for note in playlist.sanitize() {
+                    log::warn!("{}", note);
+                }
+                let playlist_id = yt.create_playlist(playlist)?;
                 println!("{}", playlist_id.as_url());
             }
-            Action::Fetch(url) => {
-                let album = source::fetch(url, &self.mp3_dir())?;
-                self.store()?.save(&album)?;
+            Action::Fetch(url, overrides) => {
+                let mut store = self.store()?;
+                let mut album = flow::fetch(&self, &mut store, url)?;
+                overrides.apply(&mut album);
+                store.save(&album)?;
             }
             Action::Video {
                 input,
                 image,
                 output,
             } => {
-                video::convert_file(input, image, output)?;
+                video::convert_file(input, image, output, &self.profile, None)?;
                 println!("{:?}", output.canonicalize()?);
             }
-            Action::URL(url) => {
-                flow::run_url(&self, &mut self.store()?, &self.yt()?, url)?;
+            Action::URL(url, overrides) => {
+                let mut store = self.store()?;
+                if !overrides.is_empty() {
+                    let mut album = match store.get_album(url)? {
+                        Some(album) => album,
+                        None => flow::fetch(&self, &mut store, url)?,
+                    };
+                    overrides.apply(&mut album);
+                    store.save(&album)?;
+                }
+                match flow::run_url(&self, &mut store, &*self.yt()?, url)? {
+                    flow::Outcome::Done => (),
+                    flow::Outcome::Blacklisted => return Err(util::Error::new("Blacklisted")),
+                    flow::Outcome::NoLicense => return Err(util::Error::new("No license")),
+                    flow::Outcome::LicenseRejected(reason) => {
+                        return Err(util::Error::new(&reason))
+                    }
+                }
             }
-            Action::Daemon => {
-                flow::daemon(&self, &mut self.store()?, &self.yt()?)?;
+            Action::Daemon { poll } => {
+                flow::daemon(&self, &mut self.store()?, &*self.yt()?, *poll)?;
             }
-            Action::Status(url) => match self.store()?.get_album(url)? {
-                None => {
-                    println!("Not in database");
+            Action::Preview { url, track } => {
+                let album = match self.store()?.get_album(url)? {
+                    Some(album) => album,
+                    None => return Err(util::Error::new("Not in database")),
+                };
+                let dir = self.templates_dir();
+                let tracks = match track {
+                    Some(n) if *n >= 1 && *n <= album.tracks.len() => vec![n - 1],
+                    Some(n) => return Err(util::Error::new(&format!("No track {}", n))),
+                    None => (0..album.tracks.len()).collect(),
+                };
+                let mut parts = vec![];
+                for kind in &[
+                    TemplateKind::PlaylistTitle,
+                    TemplateKind::PlaylistDescription,
+                ] {
+                    parts.push((kind.file_name().to_string(), *kind, None));
+                }
+                for i in tracks {
+                    for kind in &[TemplateKind::VideoTitle, TemplateKind::VideoDescription] {
+                        let header = format!("{} (track {})", kind.file_name(), i + 1);
+                        parts.push((header, *kind, Some(i)));
+                    }
+                }
+                for (header, kind, i) in parts {
+                    println!("--- {}", header);
+                    println!("{}", source::render_template(&dir, kind, &album, i)?);
+                }
+            }
+            Action::Queue(queue_action) => self.run_queue(queue_action)?,
+            Action::Blacklist(blacklist_action) => self.run_blacklist(blacklist_action)?,
+            Action::History { since, until, url } => {
+                let events = self
+                    .store()?
+                    .upload_history(*since, *until, url.as_deref())?;
+                for ev in events {
+                    let target = match ev.track {
+                        Some(n) => format!("{} #{:02}", ev.album_url, n),
+                        None => ev.album_url.clone(),
+                    };
+                    let result = match (&ev.error, &ev.youtube_id) {
+                        (Some(e), _) => format!("failed: {}", e),
+                        (None, Some(id)) => id.clone(),
+                        (None, None) => "ok".to_string(),
+                    };
+                    println!(
+                        "{}  {:11} {:>5}s {:>2}x {:>9}  {}  {}",
+                        ev.started
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S"),
+                        ev.kind,
+                        (ev.finished - ev.started).num_seconds(),
+                        ev.attempts,
+                        ev.bytes
+                            .map(|b| format!("{:.1} MB", b as f64 / 1_000_000.0))
+                            .unwrap_or_default(),
+                        target,
+                        result
+                    );
+                }
+            }
+            Action::Status(url) => {
+                let mut store = self.store()?;
+                match store.get_album(url)? {
+                    None => {
+                        println!("Not in database");
+                        if let Some((date, error)) = store.fetch_error(url)? {
+                            println!("Fetch failed:   {}", date);
+                            for line in error.lines() {
+                                println!("  {}", line);
+                            }
+                        }
+                    }
+                    Some(album) => {
+                        album.print();
+                        println!("");
+                        println!("Has all mp3s:   {:?}", album.has_mp3(&self.mp3_dir()));
+                        println!("Has all videos: {:?}", album.has_video(&self.video_dir()));
+                    }
+                }
+                match store.queue_status(url)? {
+                    None => println!("Queue:          not queued"),
+                    Some(status) => {
+                        println!(
+                            "Queue:          {} (priority {}, {} attempts)",
+                            status.state, status.priority, status.attempts
+                        );
+                        if let Some(not_before) = status.not_before {
+                            println!("Retry after:    {}", not_before);
+                        }
+                        if let Some(result) = status.result {
+                            println!("Last result:    {}", status.result_date.unwrap_or_default());
+                            // multi-line results such as tracklist diffs
+                            for line in result.lines() {
+                                println!("  {}", line);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_blacklist(&self, action: &BlacklistAction) -> util::Result<()> {
+        let mut store = self.store()?;
+        match action {
+            BlacklistAction::Add(rule) => {
+                if !store.blacklist_add(rule)? {
+                    println!("Already present: {}", rule);
+                }
+            }
+            BlacklistAction::Remove(rule) => {
+                if !store.blacklist_remove(rule)? {
+                    return Err(util::Error::new(&format!("No such rule: {}", rule)));
+                }
+            }
+            BlacklistAction::List => {
+                for rule in store.blacklist_rules()? {
+                    println!("{}", rule);
                 }
-                Some(album) => {
-                    album.print();
-                    println!("");
-                    println!("Has all mp3s:   {:?}", album.has_mp3(&self.mp3_dir()));
-                    println!("Has all videos: {:?}", album.has_video(&self.video_dir()));
+            }
+            BlacklistAction::Test(url) => {
+                let album = match store.get_album(url)? {
+                    Some(album) => album,
+                    None => return Err(util::Error::new("Not in database")),
+                };
+                match store.blacklist()?.explain(&album) {
+                    model::Verdict::Clean => println!("Not blacklisted"),
+                    model::Verdict::Denied(deny) => println!("Blacklisted by {}", deny),
+                    model::Verdict::Allowed { allow, deny } => {
+                        println!("Allowed by {}", allow);
+                        println!("despite {}", deny);
+                    }
                 }
-            },
+            }
         }
 
         Ok(())
     }
+
+    fn run_queue(&self, action: &QueueAction) -> util::Result<()> {
+        let mut store = self.store()?;
+        let not_queued = |url: &str| util::Error::new(&format!("Not queued: {}", url));
+        match action {
+            QueueAction::Add { urls, priority } => {
+                for url in urls {
+                    if store.queue_insert(url, *priority)? {
+                        println!("Added {}", url);
+                    } else {
+                        println!("Already queued {}", url);
+                    }
+                }
+            }
+            QueueAction::List(states) => {
+                for st in store.queue_list(states)? {
+                    println!(
+                        "{:<18} {:>4} {:>3}  {}",
+                        st.state, st.priority, st.attempts, st.url
+                    );
+                    // failure reason, full text is in status
+                    match st.result.as_deref().and_then(|r| r.lines().next()) {
+                        Some("OK") | None => (),
+                        Some(line) => println!("{:28}{}", "", line),
+                    }
+                }
+            }
+            QueueAction::Retry(urls) if urls.is_empty() => {
+                println!("Retrying {} failed URLs", store.queue_retry_failed()?);
+            }
+            QueueAction::RetryLicense => {
+                println!(
+                    "Retrying {} URLs skipped by license policy",
+                    store.queue_retry_license()?
+                );
+            }
+            QueueAction::Retry(urls) => {
+                for url in urls {
+                    if !store.queue_retry(url)? {
+                        return Err(util::Error::new(&format!(
+                            "Not queued or in progress: {}",
+                            url
+                        )));
+                    }
+                }
+            }
+            QueueAction::Remove(urls) => {
+                for url in urls {
+                    if !store.queue_remove(url)? {
+                        return Err(not_queued(url));
+                    }
+                }
+            }
+            QueueAction::Bump(urls) => {
+                for url in urls {
+                    if !store.queue_bump(url)? {
+                        return Err(not_queued(url));
+                    }
+                }
+            }
+            QueueAction::Stats => {
+                let stats = store.queue_stats()?;
+                for (state, n) in &stats {
+                    println!("{:<18} {:>6}", state, n);
+                }
+                println!(
+                    "{:<18} {:>6}",
+                    "total",
+                    stats.iter().map(|(_, n)| n).sum::<u32>()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Date without time means local midnight, at the end of the day if end_of_day.
+fn parse_time(s: &str, end_of_day: bool) -> util::Result<chrono::DateTime<chrono::Utc>> {
+    use chrono::TimeZone;
+
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&chrono::Utc));
+    }
+    let mut date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| util::Error::new(&format!("Invalid date {}", s)))?;
+    if end_of_day {
+        date = date.succ();
+    }
+    chrono::Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .ok_or_else(|| util::Error::new(&format!("Invalid local date {}", s)))
 }
 
 impl Default for Config {
@@ -219,6 +717,12 @@ impl Default for Config {
         Config {
             verbose: 0,
             appdir: appdir,
+            fake_youtube: false,
+            audio_format: model::AudioFormat::Mp3,
+            profile: video::Profile::default(),
+            album_video: None,
+            jobs: 1,
+            license_policy: license::Policy::default(),
             action: Action::Help,
         }
     }
@@ -1,40 +1,92 @@
 use crate::config;
+use crate::license;
+use crate::model;
 use crate::source;
 use crate::store;
+use crate::template::{self, TemplateKind};
 use crate::util;
 use crate::video;
 use crate::youtube;
 
-pub fn run_url(
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+// Albums that are skipped rather than failed.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Done,
+    Blacklisted,
+    NoLicense,
+    LicenseRejected(String), // by the license policy
+}
+
+// Fetches the album, the error is kept in the store so that status can show why an album that
+// never got saved was rejected.
+pub fn fetch(
     config: &config::Config,
     store: &mut store::Store,
-    yt: &youtube::YT,
     url: &str,
-) -> util::Result<()> {
+) -> util::Result<model::Album> {
+    source::fetch(url, &config.mp3_dir(), config.audio_format).inspect_err(|e| {
+        if let Err(se) = store.fetch_error_set(url, &e.to_string()) {
+            log::error!("Failed to record fetch error: {}", se);
+        }
+    })
+}
+
+pub fn run_url<U: youtube::Uploader + ?Sized>(
+    config: &config::Config,
+    store: &mut store::Store,
+    yt: &U,
+    url: &str,
+) -> util::Result<Outcome> {
     let yt_sleep_duration = chrono::Duration::hours(4);
     let blacklist = store.blacklist()?; // maybe don't init this every time in daemon
 
     log::info!("Processing {}", url);
     let mut album = match store.get_album(url)? {
-        None => source::fetch(url, &config.mp3_dir())?,
-        Some(album) => {
-            if album.has_mp3(&config.mp3_dir()) {
-                album
-            } else {
-                // FIXME deletes yt ids!
+        None => fetch(config, store, url)?,
+        Some(mut album) => {
+            if !album.has_mp3(&config.mp3_dir()) {
                 log::warn!("Album has missing audio files, re-fetching");
-                return Err(util::Error::new("Refetching overwrites YT ids, FIXME!"));
-                // source::fetch(url, &config.mp3_dir())?
+                let album_mp3_dir = album.dirname(&config.mp3_dir());
+                if album_mp3_dir.exists() {
+                    std::fs::remove_dir_all(&album_mp3_dir)?;
+                }
+                let fresh = fetch(config, store, url)?;
+                let old_video_dir = album.dirname(&config.video_dir());
+                for diff in album.merge_refetched(fresh)? {
+                    log::warn!("Refetched tracklist differs: {}", diff);
+                }
+                let new_video_dir = album.dirname(&config.video_dir());
+                if old_video_dir != new_video_dir && old_video_dir.exists() {
+                    log::info!("Moving {:?} to {:?}", old_video_dir, new_video_dir);
+                    std::fs::rename(&old_video_dir, &new_video_dir)?;
+                }
             }
+            album
         }
     };
     store.save(&album)?;
 
-    if album.license.is_none() {
-        return Err(util::Error::new("No license"));
+    let license = match &album.license {
+        None => return Ok(Outcome::NoLicense),
+        Some(l) => license::License::parse(l),
+    };
+    if let Err(e) = config.license_policy.check(&license) {
+        log::info!("{}", e);
+        return Ok(Outcome::LicenseRejected(e.to_string()));
     }
-    if blacklist.matches(&album) {
-        return Err(util::Error::new("Blacklisted"));
+    match blacklist.explain(&album) {
+        model::Verdict::Clean => (),
+        model::Verdict::Denied(deny) => {
+            log::info!("Blacklisted by {}", deny);
+            return Ok(Outcome::Blacklisted);
+        }
+        model::Verdict::Allowed { allow, deny } => {
+            log::info!("Allowed by {} despite {}", allow, deny);
+        }
     }
 
     let album_video_dir = album.dirname(&config.video_dir());
@@ -42,32 +94,53 @@ pub fn run_url(
         let cover_img = video::find_cover(&album.dirname(&config.mp3_dir()))?;
         let album_mp3_dir = album.dirname(&config.mp3_dir());
         util::mkdir_if_not_exists(&album_video_dir);
+        // leftovers of a run that got killed
+        video::remove_temp_files(&album_video_dir)?;
 
-        for mut tr in &mut album.tracks {
+        let mut jobs = vec![];
+        for (i, tr) in album.tracks.iter().enumerate() {
+            // finished before an earlier run failed
+            if let Some(f) = &tr.video_file {
+                if album_video_dir.join(f).exists() {
+                    continue;
+                }
+            }
             let basename = tr.mp3_file.as_ref().ok_or("MP3 file missing")?;
-            let mut mp3_file = album_mp3_dir.clone();
-            mp3_file.push(basename);
-
-            let mut video_file = album_video_dir.clone();
-            let basename = basename.with_extension("avi");
-            video_file.push(basename.clone());
-
-            video::convert_file(&mp3_file, &cover_img, &video_file)?;
-
-            tr.video_file = Some(basename);
+            let mp3_file = album_mp3_dir.join(basename);
+            let basename = basename.with_extension(config.profile.extension(&mp3_file));
+            jobs.push(RenderJob {
+                track: i,
+                mp3_file,
+                video_file: album_video_dir.join(&basename),
+                basename,
+                caption: video::caption(&album, i),
+            });
         }
-        store.save(&album)?;
+        render_tracks(config, store, &mut album, &jobs, |job| {
+            video::convert_file(
+                &job.mp3_file,
+                &cover_img,
+                &job.video_file,
+                &config.profile,
+                Some(&job.caption),
+            )
+        })?;
     }
     //TODO: can delete mp3s here
 
-    // generate descriptions first, can't use reference to album inside the for loop
-    let descriptions = album
-        .tracks
-        .iter()
-        .map(|t| source::description(&album, t))
-        .collect::<Result<Vec<_>, _>>()?;
+    // render templates first, can't use reference to album inside the for loop
+    let templates_dir = config.templates_dir();
+    let render = |kind, track| source::render_template(&templates_dir, kind, &album, track);
+    let metadata = (0..album.tracks.len())
+        .map(|i| {
+            Ok((
+                render(TemplateKind::VideoTitle, Some(i))?,
+                render(TemplateKind::VideoDescription, Some(i))?,
+            ))
+        })
+        .collect::<util::Result<Vec<_>>>()?;
 
-    for (i, desc) in descriptions.into_iter().enumerate() {
+    for (i, (title, desc)) in metadata.into_iter().enumerate() {
         let tr = album.tracks[i].clone();
         if let Some(yt_id) = &tr.youtube_id {
             log::debug!(
@@ -80,22 +153,34 @@ pub fn run_url(
 
         let mut video_file = album_video_dir.clone();
         video_file.push(tr.video_file.as_ref().ok_or("Video file missing")?);
-        let args = youtube::Video {
-            title: format!("{} - {}", tr.artist, tr.title),
+        let mut args = youtube::Video {
+            title,
             description: desc,
             tags: album.tags.clone(),
             filename: video_file,
         };
-        let yt_id = util::retry(8, yt_sleep_duration, || yt.upload_video(args.clone()))?;
+        for note in args.sanitize() {
+            log::warn!("Track {} {}", i + 1, note);
+        }
+        let bytes = std::fs::metadata(&args.filename).ok().map(|m| m.len());
+        let yt_id = logged_upload(
+            store,
+            store::UploadKind::Video,
+            (url, Some(i as u32 + 1)),
+            bytes,
+            yt_sleep_duration,
+            || yt.upload_video(args.clone()),
+        )?;
         album.tracks[i].youtube_id = Some(yt_id);
         store.save(&album)?;
     }
     //TODO: can delete videos here
 
     if album.youtube_id.is_none() && album.tracks.iter().all(|t| t.youtube_id.is_some()) {
-        let args = youtube::Playlist {
-            title: youtube::playlist_title(&album.title, &album.artist, &album.year, &album.tags),
-            description: String::new(), // the description is not really visible
+        let render = |kind| source::render_template(&templates_dir, kind, &album, None);
+        let mut args = youtube::Playlist {
+            title: render(TemplateKind::PlaylistTitle)?,
+            description: render(TemplateKind::PlaylistDescription)?,
             tags: album.tags.clone(),
             videos: album
                 .tracks
@@ -103,11 +188,27 @@ pub fn run_url(
                 .map(|t| t.youtube_id.clone().expect("Video ID missing"))
                 .collect(),
         };
-        let yt_id = util::retry(8, yt_sleep_duration, || yt.create_playlist(args.clone()))?;
+        for note in args.sanitize() {
+            log::warn!("Playlist {}", note);
+        }
+        let yt_id = logged_upload(
+            store,
+            store::UploadKind::Playlist,
+            (url, None),
+            None,
+            yt_sleep_duration,
+            || yt.create_playlist(args.clone()),
+        )?;
         album.youtube_id = Some(yt_id);
         store.save(&album)?;
     }
 
+    if let Some(covers) = config.album_video {
+        if album.album_video_id.is_none() {
+            upload_album_video(config, store, yt, &mut album, covers, yt_sleep_duration)?;
+        }
+    }
+
     log::info!(
         "Success - {} - {}",
         url,
@@ -115,34 +216,518 @@ pub fn run_url(
             .youtube_id
             .map_or("(no playlist id)".to_string(), |y| y.to_string())
     );
+    Ok(Outcome::Done)
+}
+
+struct RenderJob {
+    track: usize, // index into album.tracks
+    mp3_file: PathBuf,
+    video_file: PathBuf,
+    basename: PathBuf, // of video_file
+    caption: String,
+}
+
+// Runs up to config.jobs ffmpeg processes at once and saves each track as soon as its video is
+// done. No new tracks are started after a failure, the ones in progress are finished.
+fn render_tracks<F>(
+    config: &config::Config,
+    store: &mut store::Store,
+    album: &mut model::Album,
+    jobs: &[RenderJob],
+    convert: F,
+) -> util::Result<()>
+where
+    F: Fn(&RenderJob) -> util::Result<()> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..config.jobs.max(1).min(jobs.len()) {
+            let (tx, next, failed, convert) = (tx.clone(), &next, &failed, &convert);
+            scope.spawn(move || {
+                while !failed.load(Ordering::SeqCst) {
+                    let job = match jobs.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    // util::Error can't cross threads
+                    let res = convert(job).map_err(|e| e.to_string());
+                    if res.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    if tx.send((job, res)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut first_error = None;
+        for (job, res) in rx {
+            let res = res.map_err(|e| util::Error::new(&e)).and_then(|()| {
+                let tr = &mut album.tracks[job.track];
+                tr.video_file = Some(job.basename.clone());
+                tr.profile = Some(config.profile.name.clone());
+                store.save(album)
+            });
+            if let Err(e) = res {
+                log::error!("Track {}: {}", job.track + 1, e);
+                failed.store(true, Ordering::SeqCst);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    })
+}
+
+// Renders the whole album into one video with a chapter list in the description. Durations are
+// probed every time so that the chapters match even if the video was rendered by an earlier run.
+fn upload_album_video<U: youtube::Uploader + ?Sized>(
+    config: &config::Config,
+    store: &mut store::Store,
+    yt: &U,
+    album: &mut model::Album,
+    covers: video::AlbumCovers,
+    sleep: chrono::Duration,
+) -> util::Result<()> {
+    let album_mp3_dir = album.dirname(&config.mp3_dir());
+    let audio_files = album
+        .tracks
+        .iter()
+        .map(|t| Ok(album_mp3_dir.join(t.mp3_file.as_ref().ok_or("MP3 file missing")?)))
+        .collect::<util::Result<Vec<_>>>()?;
+    let first = audio_files.first().ok_or("Album has no tracks")?;
+    let album_video_dir = album.dirname(&config.video_dir());
+    let video_file =
+        album_video_dir.join(video::album_video_basename(config.profile.extension(first)));
+
+    let durations = if video_file.exists() {
+        audio_files
+            .iter()
+            .map(|f| video::probe_duration(f))
+            .collect::<util::Result<Vec<_>>>()?
+    } else {
+        util::mkdir_if_not_exists(&album_video_dir);
+        let cover_img = video::find_cover(&album_mp3_dir)?;
+        video::convert_album(
+            &audio_files,
+            &cover_img,
+            covers,
+            &video_file,
+            &config.profile,
+        )?
+    };
+
+    let templates_dir = config.templates_dir();
+    let context = template::album_video_context(album, &durations);
+    let render = |kind| source::render_template_context(&templates_dir, kind, album, &context);
+    let mut args = youtube::Video {
+        title: render(TemplateKind::AlbumVideoTitle)?,
+        description: render(TemplateKind::AlbumVideoDescription)?,
+        tags: album.tags.clone(),
+        filename: video_file,
+    };
+    for note in args.sanitize() {
+        log::warn!("Album video {}", note);
+    }
+    let bytes = std::fs::metadata(&args.filename).ok().map(|m| m.len());
+    let yt_id = logged_upload(
+        store,
+        store::UploadKind::AlbumVideo,
+        (&album.url, None),
+        bytes,
+        sleep,
+        || yt.upload_video(args.clone()),
+    )?;
+    album.album_video_id = Some(yt_id);
+    store.save(album)?;
     Ok(())
 }
 
-pub fn daemon(
+// Uploads with retries and records the outcome in the upload history. Target is album URL and
+// track number.
+fn logged_upload<T, F>(
+    store: &mut store::Store,
+    kind: store::UploadKind,
+    target: (&str, Option<u32>),
+    bytes: Option<u64>,
+    sleep: chrono::Duration,
+    f: F,
+) -> util::Result<T>
+where
+    T: std::fmt::Display,
+    F: Fn() -> util::Result<T>,
+{
+    let started = chrono::Utc::now();
+    let (res, attempts) = util::retry_counted(8, sleep, f);
+    let ev = store::UploadEvent {
+        kind,
+        album_url: target.0.to_string(),
+        track: target.1,
+        started,
+        finished: chrono::Utc::now(),
+        bytes,
+        attempts,
+        youtube_id: res.as_ref().ok().map(|id| id.to_string()),
+        error: res.as_ref().err().map(|e| e.to_string()),
+    };
+    // don't lose the id of successful upload because of this
+    if let Err(e) = store.upload_event_insert(&ev) {
+        log::error!("Failed to record upload: {}", e);
+    }
+    res
+}
+
+// Queues albums from Ektoplazm listing page. With stop_after_known, stops after that many
+// consecutive albums that are already queued or processed.
+pub fn scrape_ektoplazm(
+    store: &mut store::Store,
+    listing: &str,
+    offset: u32,
+    stop_after_known: Option<u32>,
+) -> util::Result<()> {
+    let mut known_run = 0;
+    for (i, x) in source::EktoplazmScraper::new(listing, offset).enumerate() {
+        // let (url, tracks, zipbytes) = x?;
+        // println!("{} {}\t{}\t{}", (i as u32)+off, tracks, zipbytes, url);
+        let url = x?;
+        if let Some(stop) = stop_after_known {
+            if store.is_known(&url)? {
+                known_run += 1;
+                log::debug!("Already known: {}", url);
+                if known_run >= stop {
+                    log::info!("{} known albums in a row, stopping", known_run);
+                    break;
+                }
+                continue;
+            }
+            known_run = 0;
+        }
+        println!("{} {}", (i as u32) + offset, url);
+        store.queue_insert(&url, 0)?;
+        // doesn't make much sense now that we don't query all urls
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+    }
+    Ok(())
+}
+
+// Retriable failures are retried with exponential backoff until the attempts run out.
+const QUEUE_MAX_ATTEMPTS: u32 = 8;
+
+fn retry_after(attempts: u32) -> chrono::Duration {
+    let max = chrono::Duration::days(7);
+    let shift = attempts.saturating_sub(1).min(16);
+    std::cmp::min(chrono::Duration::hours(1 << shift), max)
+}
+
+// Processes queued URLs. Without poll interval returns once there's nothing eligible.
+pub fn daemon<U: youtube::Uploader + ?Sized>(
     config: &config::Config,
     store: &mut store::Store,
-    yt: &youtube::YT,
+    yt: &U,
+    poll: Option<std::time::Duration>,
 ) -> util::Result<()> {
+    let recovered = store.queue_recover()?;
+    if recovered > 0 {
+        log::warn!("Recovered {} interrupted queue items", recovered);
+    }
     loop {
-        let (act, url) = match store.queue_get()? {
-            None => {
-                log::error!("No more work!");
-                return Ok(());
+        let item = match store.queue_get(chrono::Utc::now())? {
+            None => match poll {
+                None => {
+                    log::error!("No more work!");
+                    return Ok(());
+                }
+                Some(interval) => {
+                    // wake up early if some retry becomes due
+                    let interval = match store.queue_next_retry()? {
+                        Some(t) => (t - chrono::Utc::now())
+                            .to_std()
+                            .map_or(interval, |d| std::cmp::min(d, interval)),
+                        None => interval,
+                    };
+                    log::debug!("Nothing to do, sleeping {:?}", interval);
+                    std::thread::sleep(interval);
+                    continue;
+                }
+            },
+            Some(item) => item,
+        };
+        if item.action != "url" {
+            let msg = format!("Unknown action {}", item.action);
+            store.queue_finish(item.id, store::QueueState::FailedPermanent, &msg, None)?;
+            return Err(util::Error::new(&msg));
+        }
+        let (state, result, not_before) = match run_url(config, store, yt, &item.url) {
+            Ok(Outcome::Done) => (store::QueueState::Done, "OK".to_string(), None),
+            Ok(Outcome::Blacklisted) => (
+                store::QueueState::SkippedBlacklist,
+                "Blacklisted".to_string(),
+                None,
+            ),
+            Ok(Outcome::NoLicense) => (
+                store::QueueState::SkippedNoLicense,
+                "No license".to_string(),
+                None,
+            ),
+            Ok(Outcome::LicenseRejected(reason)) => {
+                (store::QueueState::SkippedLicense, reason, None)
             }
-            Some((act, url)) if act == "url" => (act, url),
-            Some((act, _)) => {
-                return Err(util::Error::new(&format!("Unknown action {}", act)));
+            Err(e) if e.is_retriable() && item.attempts < QUEUE_MAX_ATTEMPTS => {
+                let not_before = chrono::Utc::now() + retry_after(item.attempts);
+                log::error!(
+                    "Processing {} failed, retrying after {}: {}",
+                    item.url,
+                    not_before,
+                    e
+                );
+                (
+                    store::QueueState::FailedRetryable,
+                    e.to_string(),
+                    Some(not_before),
+                )
             }
-        };
-        let res = run_url(config, store, yt, &url);
-        let status = match res {
             Err(e) => {
-                log::error!("Processing {} failed: {}", url, e);
-                e.to_string()
+                log::error!("Processing {} failed: {}", item.url, e);
+                (store::QueueState::FailedPermanent, e.to_string(), None)
             }
-            Ok(()) => "OK".to_string(),
         };
-        store.queue_result(act, url, status)?;
+        store.queue_finish(item.id, state, &result, not_before)?;
         std::thread::sleep(std::time::Duration::from_secs(1));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{self, Album, Track};
+    use std::path::PathBuf;
+
+    #[test]
+    fn run_url_fake_youtube() {
+        let appdir = tempfile::tempdir().unwrap();
+        let config = config::Config {
+            verbose: 0,
+            appdir: appdir.path().to_path_buf(),
+            fake_youtube: true,
+            audio_format: model::AudioFormat::Mp3,
+            license_policy: license::Policy::default(),
+            profile: video::Profile::default(),
+            album_video: None,
+            jobs: 1,
+            action: config::Action::Help,
+        };
+        let mut store = store::Store::open(&config.db_path()).unwrap();
+        let yt = youtube::FakeYT::new(&config.fake_youtube_dir());
+
+        let url = "https://ektoplazm.com/free-music/globular-entangled-everything";
+        let track = |n: u32, title: &str| Track {
+            artist: "Globular".to_string(),
+            title: title.to_string(),
+            bpm: None,
+            mp3_file: Some(PathBuf::from(format!("{:02}.mp3", n))),
+            video_file: Some(PathBuf::from(format!("{:02}.avi", n))),
+            profile: None,
+            youtube_id: None,
+        };
+        let album = Album {
+            url: url.to_string(),
+            artist: Some("Globular".to_string()),
+            title: "Entangled Everything".to_string(),
+            license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+            year: Some(2018),
+            labels: vec![],
+            tags: vec!["Downtempo".to_string(), "Psy Dub".to_string()],
+            tracks: vec![track(1, "Popping Out"), track(2, "For The Time Being")],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        for (dir, ext) in &[(config.mp3_dir(), "mp3"), (config.video_dir(), "avi")] {
+            let dir = album.dirname(dir);
+            util::mkdir_if_not_exists(&dir);
+            for n in 1..=2 {
+                std::fs::write(dir.join(format!("{:02}.{}", n, ext)), b"").unwrap();
+            }
+        }
+        store.save(&album).unwrap();
+
+        assert_eq!(
+            run_url(&config, &mut store, &yt, url).unwrap(),
+            Outcome::Done
+        );
+        let album = store.get_album(url).unwrap().unwrap();
+        assert_eq!(
+            album.youtube_id,
+            Some(youtube::PlaylistID("PLFAKE000001".to_string()))
+        );
+        assert_eq!(
+            album
+                .tracks
+                .iter()
+                .map(|t| t.youtube_id.clone().unwrap().0)
+                .collect::<Vec<_>>(),
+            vec!["FAKE0000001", "FAKE0000002"]
+        );
+        let history = store.upload_history(None, None, Some(url)).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|ev| (ev.kind, ev.track, ev.attempts, ev.youtube_id.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (store::UploadKind::Video, Some(1), 1, Some("FAKE0000001")),
+                (store::UploadKind::Video, Some(2), 1, Some("FAKE0000002")),
+                (store::UploadKind::Playlist, None, 1, Some("PLFAKE000001")),
+            ]
+        );
+        assert_eq!(history[0].bytes, Some(0));
+        let playlist: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(config.fake_youtube_dir().join("playlist-PLFAKE000001.json"))
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(playlist["description"].as_str().unwrap().contains(
+            "02. Globular - For The Time Being https://www.youtube.com/watch?v=FAKE0000002"
+        ));
+        assert!(history.iter().all(|ev| ev.error.is_none()));
+
+        // nothing is uploaded twice
+        run_url(&config, &mut store, &yt, url).unwrap();
+        assert_eq!(
+            std::fs::read_dir(config.fake_youtube_dir())
+                .unwrap()
+                .count(),
+            3
+        );
+        assert_eq!(store.upload_history(None, None, None).unwrap().len(), 3);
+    }
+
+    fn broken_album(config: &config::Config, tracks: u32) -> Album {
+        let mut album = Album {
+            url: "file:///tmp/album".to_string(),
+            artist: None,
+            title: "Broken".to_string(),
+            license: Some("https://creativecommons.org/licenses/by/4.0/".to_string()),
+            year: None,
+            labels: vec![],
+            tags: vec![],
+            tracks: vec![],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        // not audio, ffmpeg fails on every track
+        let mp3_dir = album.dirname(&config.mp3_dir());
+        util::mkdir_if_not_exists(&mp3_dir);
+        std::fs::write(mp3_dir.join("cover.jpg"), b"").unwrap();
+        for n in 1..=tracks {
+            let mp3_file = PathBuf::from(format!("{:02}.mp3", n));
+            std::fs::write(mp3_dir.join(&mp3_file), b"").unwrap();
+            album.tracks.push(Track {
+                artist: "A".to_string(),
+                title: n.to_string(),
+                bpm: None,
+                mp3_file: Some(mp3_file),
+                video_file: None,
+                profile: None,
+                youtube_id: None,
+            });
+        }
+        album
+    }
+
+    #[test]
+    fn run_url_render_failure() {
+        let appdir = tempfile::tempdir().unwrap();
+        let config = config::Config {
+            appdir: appdir.path().to_path_buf(),
+            fake_youtube: true,
+            jobs: 3,
+            ..config::Config::default()
+        };
+        let mut store = store::Store::open(&config.db_path()).unwrap();
+        let yt = youtube::FakeYT::new(&config.fake_youtube_dir());
+        let album = broken_album(&config, 5);
+        store.save(&album).unwrap();
+        let video_dir = album.dirname(&config.video_dir());
+        util::mkdir_if_not_exists(&video_dir);
+        std::fs::write(video_dir.join("TEMP 01.avi"), b"").unwrap();
+
+        // Only the leftover of a killed run is checked, ffmpeg may not even be installed so
+        // the conversions fail before writing anything. Partial files of failed conversions
+        // are covered by render_tracks_partial.
+        assert!(run_url(&config, &mut store, &yt, &album.url).is_err());
+        assert_eq!(std::fs::read_dir(&video_dir).unwrap().count(), 0);
+        let album = store.get_album(&album.url).unwrap().unwrap();
+        assert!(album.tracks.iter().all(|t| t.video_file.is_none()));
+    }
+
+    #[test]
+    fn render_tracks_partial() {
+        let appdir = tempfile::tempdir().unwrap();
+        let config = config::Config {
+            appdir: appdir.path().to_path_buf(),
+            jobs: 1,
+            ..config::Config::default()
+        };
+        let mut store = store::Store::open(&config.db_path()).unwrap();
+        let mut album = broken_album(&config, 5);
+        store.save(&album).unwrap();
+        let video_dir = album.dirname(&config.video_dir());
+        util::mkdir_if_not_exists(&video_dir);
+        let jobs = (0..5)
+            .map(|i| {
+                let basename = PathBuf::from(format!("{:02}.avi", i + 1));
+                RenderJob {
+                    track: i,
+                    mp3_file: PathBuf::new(),
+                    video_file: video_dir.join(&basename),
+                    basename,
+                    caption: String::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // fails after writing part of the video like ffmpeg would
+        let res = render_tracks(&config, &mut store, &mut album, &jobs, |job| {
+            video::write_via_temp(&job.video_file, |temp_file| {
+                std::fs::write(temp_file, b"")?;
+                if job.track == 2 {
+                    Err(util::Error::new("ffmpeg failed"))
+                } else {
+                    Ok(())
+                }
+            })
+        });
+        assert!(res.is_err());
+        let mut files = std::fs::read_dir(&video_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, vec!["01.avi", "02.avi"]);
+        let album = store.get_album(&album.url).unwrap().unwrap();
+        assert_eq!(
+            album
+                .tracks
+                .iter()
+                .map(|t| t.video_file.as_ref().map(|f| f.to_str().unwrap()))
+                .collect::<Vec<_>>(),
+            vec![Some("01.avi"), Some("02.avi"), None, None, None]
+        );
+        assert_eq!(
+            album.tracks[0].profile.as_deref(),
+            Some(config.profile.name.as_str())
+        );
+    }
+}
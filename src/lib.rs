@@ -9,9 +9,11 @@ extern crate log;
 mod cli;
 mod config;
 mod flow;
+mod license;
 mod model;
 mod source;
 mod store;
+mod template;
 mod util;
 mod video;
 mod youtube;
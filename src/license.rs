@@ -0,0 +1,286 @@
+use crate::util;
+
+use regex::Regex;
+
+// License parsed from the deed URL. Anything we don't recognize is kept as is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum License {
+    CreativeCommons(CreativeCommons),
+    Other(String),
+}
+
+// All flags are false for CC0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreativeCommons {
+    pub by: bool,
+    pub nc: bool,
+    pub nd: bool,
+    pub sa: bool,
+    pub version: String,
+    pub port: Option<String>, // jurisdiction, e.g. "ca" for 2.5/ca
+    pub url: String,
+}
+
+impl License {
+    pub fn parse(url: &str) -> License {
+        lazy_static! {
+            static ref CC_RE: Regex = Regex::new(
+                r"(?i)^https?://(?:www\.)?creativecommons\.org/licenses/([a-z-]+)/(\d+\.\d+)(?:/([a-z]{2}(?:-[a-z]{2})?))?/?(?:deed\.[a-z_-]+|legalcode)?$"
+            )
+            .unwrap();
+            static ref CC0_RE: Regex = Regex::new(
+                r"(?i)^https?://(?:www\.)?creativecommons\.org/publicdomain/zero/(\d+\.\d+)/?(?:deed\.[a-z_-]+|legalcode)?$"
+            )
+            .unwrap();
+        }
+
+        if let Some(c) = CC0_RE.captures(url) {
+            return License::CreativeCommons(CreativeCommons {
+                by: false,
+                nc: false,
+                nd: false,
+                sa: false,
+                version: c[1].to_string(),
+                port: None,
+                url: url.to_string(),
+            });
+        }
+        if let Some(c) = CC_RE.captures(url) {
+            let code = c[1].to_lowercase();
+            let elements: Vec<&str> = code.split('-').collect();
+            let valid = elements.first() == Some(&"by")
+                && elements
+                    .iter()
+                    .skip(1)
+                    .all(|e| ["nc", "nd", "sa"].contains(e))
+                && !(elements.contains(&"nd") && elements.contains(&"sa"));
+            if valid {
+                return License::CreativeCommons(CreativeCommons {
+                    by: true,
+                    nc: elements.contains(&"nc"),
+                    nd: elements.contains(&"nd"),
+                    sa: elements.contains(&"sa"),
+                    version: c[2].to_string(),
+                    port: c.get(3).map(|p| p.as_str().to_lowercase()),
+                    url: url.to_string(),
+                });
+            }
+        }
+        License::Other(url.to_string())
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            License::CreativeCommons(cc) => &cc.url,
+            License::Other(url) => url,
+        }
+    }
+
+    // Short name such as "CC BY-NC-SA 4.0", None if we don't know it.
+    pub fn name(&self) -> Option<String> {
+        let cc = match self {
+            License::CreativeCommons(cc) => cc,
+            License::Other(_) => return None,
+        };
+        if !cc.by {
+            return Some(format!("CC0 {}", cc.version));
+        }
+        let mut res = "CC BY".to_string();
+        for (flag, code) in &[(cc.nc, "-NC"), (cc.nd, "-ND"), (cc.sa, "-SA")] {
+            if *flag {
+                res.push_str(code);
+            }
+        }
+        res.push(' ');
+        res.push_str(&cc.version);
+        if let Some(port) = &cc.port {
+            res.push(' ');
+            res.push_str(&port.to_uppercase());
+        }
+        Some(res)
+    }
+
+    // Credit line for the description of a work.
+    pub fn attribution(&self, title: &str, author: &str) -> String {
+        match (self, self.name()) {
+            (License::CreativeCommons(cc), Some(name)) if !cc.by => format!(
+                "\"{}\" by {} is dedicated to the public domain ({}): {}",
+                title, author, name, cc.url
+            ),
+            (License::CreativeCommons(cc), Some(name)) => format!(
+                "\"{}\" by {} is licensed under {}: {}",
+                title, author, name, cc.url
+            ),
+            _ => format!("License: {}", self.url()),
+        }
+    }
+}
+
+impl std::fmt::Display for License {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({})", name, self.url()),
+            None => write!(f, "{}", self.url()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Restriction {
+    NonCC, // we can't tell whether redistribution is allowed
+    NonCommercial,
+    NoDerivatives,
+    ShareAlike,
+}
+
+impl Restriction {
+    pub fn from_name(name: &str) -> Option<Restriction> {
+        match name {
+            "non-cc" => Some(Restriction::NonCC),
+            "nc" => Some(Restriction::NonCommercial),
+            "nd" => Some(Restriction::NoDerivatives),
+            "sa" => Some(Restriction::ShareAlike),
+            _ => None,
+        }
+    }
+
+    fn applies(self, license: &License) -> bool {
+        match (self, license) {
+            (Restriction::NonCC, License::Other(_)) => true,
+            (Restriction::NonCommercial, License::CreativeCommons(cc)) => cc.nc,
+            (Restriction::NoDerivatives, License::CreativeCommons(cc)) => cc.nd,
+            (Restriction::ShareAlike, License::CreativeCommons(cc)) => cc.sa,
+            _ => false,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Restriction::NonCC => "not a Creative Commons license",
+            Restriction::NonCommercial => "non-commercial only",
+            Restriction::NoDerivatives => "no derivative works",
+            Restriction::ShareAlike => "share-alike",
+        }
+    }
+}
+
+// Which licenses we upload under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub reject: Vec<Restriction>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            reject: vec![Restriction::NonCC],
+        }
+    }
+}
+
+impl Policy {
+    pub fn check(&self, license: &License) -> util::Result<()> {
+        match self.reject.iter().find(|r| r.applies(license)) {
+            None => Ok(()),
+            Some(r) => Err(util::Error::new(&format!(
+                "License not allowed, {}: {}",
+                r.describe(),
+                license
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let l = License::parse("https://creativecommons.org/licenses/by-nc-sa/4.0/");
+        assert_eq!(
+            l,
+            License::CreativeCommons(CreativeCommons {
+                by: true,
+                nc: true,
+                nd: false,
+                sa: true,
+                version: "4.0".to_string(),
+                port: None,
+                url: "https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string(),
+            })
+        );
+        assert_eq!(l.name(), Some("CC BY-NC-SA 4.0".to_string()));
+
+        let names = [
+            (
+                "http://creativecommons.org/licenses/by-nc-nd/2.5/ca/",
+                "CC BY-NC-ND 2.5 CA",
+            ),
+            ("https://creativecommons.org/licenses/by/3.0", "CC BY 3.0"),
+            (
+                "https://creativecommons.org/licenses/by-sa/3.0/deed.en",
+                "CC BY-SA 3.0",
+            ),
+            (
+                "https://creativecommons.org/publicdomain/zero/1.0/",
+                "CC0 1.0",
+            ),
+        ];
+        for (url, name) in &names {
+            assert_eq!(License::parse(url).name(), Some(name.to_string()));
+        }
+
+        for url in &[
+            "https://creativecommons.org/licenses/nc-sa/1.0/",
+            "https://creativecommons.org/licenses/by-nd-sa/4.0/",
+            "https://creativecommons.org/licenses/by/4.0/foo/bar",
+            "https://example.com/licenses/by/4.0/",
+            "All rights reserved",
+        ] {
+            assert_eq!(License::parse(url), License::Other(url.to_string()));
+        }
+    }
+
+    #[test]
+    fn attribution() {
+        let l = License::parse("https://creativecommons.org/licenses/by-nc-nd/2.5/ca/");
+        assert_eq!(
+            l.attribution("Digital Being", "Risingson"),
+            "\"Digital Being\" by Risingson is licensed under CC BY-NC-ND 2.5 CA: \
+             https://creativecommons.org/licenses/by-nc-nd/2.5/ca/"
+        );
+        let l = License::parse("https://creativecommons.org/publicdomain/zero/1.0/");
+        assert_eq!(
+            l.attribution("Digital Being", "Risingson"),
+            "\"Digital Being\" by Risingson is dedicated to the public domain (CC0 1.0): \
+             https://creativecommons.org/publicdomain/zero/1.0/"
+        );
+        let l = License::parse("https://example.com/eula");
+        assert_eq!(
+            l.attribution("Digital Being", "Risingson"),
+            "License: https://example.com/eula"
+        );
+    }
+
+    #[test]
+    fn policy() {
+        let by_nd = License::parse("https://creativecommons.org/licenses/by-nd/4.0/");
+        let by_sa = License::parse("https://creativecommons.org/licenses/by-sa/4.0/");
+        let other = License::parse("https://example.com/eula");
+
+        let default = Policy::default();
+        assert!(default.check(&by_nd).is_ok());
+        assert!(default.check(&by_sa).is_ok());
+        assert!(default.check(&other).is_err());
+
+        let strict = Policy {
+            reject: vec![Restriction::NoDerivatives, Restriction::NonCommercial],
+        };
+        assert!(strict.check(&by_nd).is_err());
+        assert!(strict.check(&by_sa).is_ok());
+        assert!(strict.check(&other).is_ok());
+
+        assert!(Policy { reject: vec![] }.check(&other).is_ok());
+    }
+}
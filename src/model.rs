@@ -1,3 +1,4 @@
+use crate::license;
 use crate::util;
 use crate::youtube;
 
@@ -30,6 +31,21 @@ pub struct Album {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub youtube_id: Option<youtube::PlaylistID>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<chrono::NaiveDate>, // web
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>, // web, short blurb
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>, // archive the audio came from
+
+    // whole album in one video, youtube_id is the playlist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_video_id: Option<youtube::VideoID>,
+
+    // differences between the web page and the files that were tolerated when fetching
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
 }
 
 impl Album {
@@ -76,6 +92,119 @@ impl Album {
         }
     }
 
+    // Replaces metadata and audio files with those from freshly fetched album while keeping YouTube
+    // ids and video files. Tracks are matched by number and title, returns list of differences.
+    pub fn merge_refetched(&mut self, fresh: Album) -> Result<Vec<String>, util::Error> {
+        let same_title =
+            |a: &Track, b: &Track| a.title.trim().to_lowercase() == b.title.trim().to_lowercase();
+        let mut matched: Vec<Option<usize>> = vec![None; fresh.tracks.len()];
+        let mut used = vec![false; self.tracks.len()];
+        let mut report = Vec::new();
+
+        // same number and title
+        for (i, ft) in fresh.tracks.iter().enumerate() {
+            if let Some(st) = self.tracks.get(i) {
+                if same_title(st, ft) {
+                    matched[i] = Some(i);
+                    used[i] = true;
+                }
+            }
+        }
+
+        // same title, different number
+        for (i, ft) in fresh.tracks.iter().enumerate() {
+            if matched[i].is_some() {
+                continue;
+            }
+            if let Some(j) =
+                (0..self.tracks.len()).find(|&j| !used[j] && same_title(&self.tracks[j], ft))
+            {
+                report.push(format!(
+                    "track {:02} {:?} was track {:02}",
+                    i + 1,
+                    ft.title,
+                    j + 1
+                ));
+                matched[i] = Some(j);
+                used[j] = true;
+            }
+        }
+
+        // same number, different title
+        for (i, ft) in fresh.tracks.iter().enumerate() {
+            if matched[i].is_some() || i >= self.tracks.len() || used[i] {
+                continue;
+            }
+            report.push(format!(
+                "track {:02} title changed from {:?} to {:?}",
+                i + 1,
+                self.tracks[i].title,
+                ft.title
+            ));
+            matched[i] = Some(i);
+            used[i] = true;
+        }
+
+        for (i, ft) in fresh.tracks.iter().enumerate() {
+            if matched[i].is_none() {
+                report.push(format!("track {:02} {:?} is new", i + 1, ft.title));
+            }
+        }
+        let mut lost = false;
+        for (j, st) in self.tracks.iter().enumerate() {
+            if !used[j] {
+                report.push(format!(
+                    "track {:02} {:?} no longer present",
+                    j + 1,
+                    st.title
+                ));
+                lost = lost || st.youtube_id.is_some();
+            }
+        }
+        if lost {
+            return Err(util::Error::new(&format!(
+                "Refetched tracklist would lose YouTube ids: {}",
+                report.join("; ")
+            )));
+        }
+
+        // changes dirname, the caller has to move the videos
+        if (&self.artist, &self.title) != (&fresh.artist, &fresh.title) {
+            let name = |a: &Album| format!("{} - {}", a.artist.as_deref().unwrap_or("VA"), a.title);
+            report.push(format!(
+                "album renamed from {:?} to {:?}",
+                name(self),
+                name(&fresh)
+            ));
+        }
+
+        let mut tracks = fresh.tracks;
+        for (i, tr) in tracks.iter_mut().enumerate() {
+            if let Some(j) = matched[i] {
+                tr.youtube_id = self.tracks[j].youtube_id.clone();
+                tr.video_file = self.tracks[j].video_file.clone();
+                tr.profile = self.tracks[j].profile.clone();
+            }
+        }
+
+        self.artist = fresh.artist;
+        self.title = fresh.title;
+        // keep metadata that might have been supplied by hand
+        self.license = fresh.license.or_else(|| self.license.take());
+        self.year = fresh.year;
+        if !fresh.labels.is_empty() {
+            self.labels = fresh.labels;
+        }
+        self.tags = fresh.tags;
+        self.tracks = tracks;
+        self.release_date = fresh.release_date.or(self.release_date);
+        self.description = fresh.description.or_else(|| self.description.take());
+        self.download_url = fresh.download_url;
+        self.notes = fresh.notes;
+
+        Ok(report)
+    }
+
     pub fn print(&self) {
         let nf = "(none found)".to_string();
         println!(
@@ -87,7 +216,18 @@ impl Album {
             "Year:    {}",
             self.year.map(|n| n.to_string()).unwrap_or(nf.clone())
         );
-        println!("License: {}", self.license.as_ref().unwrap_or(&nf));
+        println!(
+            "Date:    {}",
+            self.release_date
+                .map(|d| d.to_string())
+                .unwrap_or(nf.clone())
+        );
+        println!(
+            "License: {}",
+            self.license
+                .as_ref()
+                .map_or(nf.clone(), |l| license::License::parse(l).to_string())
+        );
         println!(
             "Label:   {}",
             if self.labels.is_empty() {
@@ -111,6 +251,15 @@ impl Album {
                 .map(|i| i.as_url())
                 .unwrap_or(nf.clone())
         );
+        if let Some(id) = &self.album_video_id {
+            println!("Full YT: {}", id.as_url());
+        }
+        if !self.notes.is_empty() {
+            println!("Notes:");
+            for note in &self.notes {
+                println!("  {}", note);
+            }
+        }
         println!("Tracks:");
         for (i, t) in self.tracks.iter().enumerate() {
             let tnum = i + 1;
@@ -136,6 +285,9 @@ impl Album {
                     f.clone().into_os_string().to_string_lossy()
                 );
             }
+            if let Some(p) = &t.profile {
+                println!("       Profile: {}", p);
+            }
             println!(
                 "       YT:    {}",
                 t.youtube_id
@@ -147,6 +299,13 @@ impl Album {
     }
 }
 
+// Format of the audio files downloaded from the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    Mp3,
+    Flac,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Track {
     pub artist: String,
@@ -156,7 +315,7 @@ pub struct Track {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bpm: Option<u16>,
 
-    // relative to mp3_subdir
+    // relative to mp3_subdir, can be FLAC as well
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mp3_file: Option<PathBuf>,
 
@@ -164,54 +323,162 @@ pub struct Track {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub video_file: Option<PathBuf>,
 
+    // name of the video profile that rendered video_file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub youtube_id: Option<youtube::VideoID>,
 }
 
-pub struct Blacklist {
-    artists: Vec<Regex>,
-    labels: Vec<Regex>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlacklistField {
+    Artist, // of any track
+    Label,
+    Tag,
+    AlbumTitle,
+    Url,
 }
 
-impl Blacklist {
-    pub fn new<T>(artists: T, labels: T) -> Result<Blacklist, util::Error>
-    where
-        T: IntoIterator,
-        T::Item: AsRef<str>,
-    {
-        let mut res = Blacklist {
-            artists: vec![],
-            labels: vec![],
-        };
-        let pat = |s: T::Item| format!(r"(?i)^{}$", s.as_ref());
+const BLACKLIST_FIELDS: [BlacklistField; 5] = [
+    BlacklistField::Artist,
+    BlacklistField::Label,
+    BlacklistField::Tag,
+    BlacklistField::AlbumTitle,
+    BlacklistField::Url,
+];
 
-        for s in artists {
-            res.artists.push(Regex::new(&pat(s))?);
-        }
-        for s in labels {
-            res.labels.push(Regex::new(&pat(s))?);
+impl BlacklistField {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BlacklistField::Artist => "artist",
+            BlacklistField::Label => "label",
+            BlacklistField::Tag => "tag",
+            BlacklistField::AlbumTitle => "album-title",
+            BlacklistField::Url => "url",
         }
-        Ok(res)
     }
 
-    pub fn matches(&self, album: &Album) -> bool {
-        if self
-            .labels
+    pub fn from_name(name: &str) -> Option<BlacklistField> {
+        BLACKLIST_FIELDS
             .iter()
-            .any(|r| album.labels.iter().any(|l| r.is_match(l)))
-        {
-            return true;
+            .cloned()
+            .find(|f| f.as_str() == name)
+    }
+
+    fn values(self, album: &Album) -> Vec<&str> {
+        match self {
+            BlacklistField::Artist => album.tracks.iter().map(|t| t.artist.as_str()).collect(),
+            BlacklistField::Label => album.labels.iter().map(String::as_str).collect(),
+            BlacklistField::Tag => album.tags.iter().map(String::as_str).collect(),
+            BlacklistField::AlbumTitle => vec![album.title.as_str()],
+            BlacklistField::Url => vec![album.url.as_str()],
         }
+    }
+}
 
-        if self
-            .artists
-            .iter()
-            .any(|r| album.tracks.iter().any(|t| r.is_match(&t.artist)))
-        {
-            return true;
+impl std::fmt::Display for BlacklistField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+// Case-insensitive regex that has to match the whole value. Allow rules are exceptions: an
+// album matching any of them is never blacklisted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlacklistRule {
+    pub field: BlacklistField,
+    pub pattern: String,
+    pub allow: bool,
+}
+
+impl BlacklistRule {
+    pub fn deny(field: BlacklistField, pattern: &str) -> BlacklistRule {
+        BlacklistRule {
+            field,
+            pattern: pattern.to_string(),
+            allow: false,
+        }
+    }
+
+    pub fn allow(field: BlacklistField, pattern: &str) -> BlacklistRule {
+        BlacklistRule {
+            allow: true,
+            ..BlacklistRule::deny(field, pattern)
         }
+    }
+}
+
+impl std::fmt::Display for BlacklistRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = if self.allow { "allow" } else { "deny" };
+        write!(f, "{} {} {}", kind, self.field, self.pattern)
+    }
+}
+
+// Rule together with the album field value it matched.
+#[derive(Debug, PartialEq)]
+pub struct RuleMatch<'a> {
+    pub rule: &'a BlacklistRule,
+    pub value: String,
+}
 
-        return false;
+impl std::fmt::Display for RuleMatch<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (matched \"{}\")", self.rule, self.value)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict<'a> {
+    Clean,
+    Denied(RuleMatch<'a>),
+    Allowed {
+        allow: RuleMatch<'a>,
+        deny: RuleMatch<'a>,
+    },
+}
+
+pub struct Blacklist {
+    rules: Vec<(BlacklistRule, Regex)>,
+}
+
+impl Blacklist {
+    pub fn new(rules: Vec<BlacklistRule>) -> Result<Blacklist, util::Error> {
+        let mut res = Blacklist { rules: vec![] };
+        for rule in rules {
+            let re = Regex::new(&format!(r"(?i)^(?:{})$", rule.pattern))?;
+            res.rules.push((rule, re));
+        }
+        Ok(res)
+    }
+
+    // First matching deny rule and the allow rule overriding it, if any.
+    pub fn explain(&self, album: &Album) -> Verdict<'_> {
+        let deny = match self.first_match(album, false) {
+            None => return Verdict::Clean,
+            Some(m) => m,
+        };
+        match self.first_match(album, true) {
+            None => Verdict::Denied(deny),
+            Some(allow) => Verdict::Allowed { allow, deny },
+        }
+    }
+
+    fn first_match(&self, album: &Album, allow: bool) -> Option<RuleMatch<'_>> {
+        self.rules
+            .iter()
+            .filter(|(rule, _)| rule.allow == allow)
+            .find_map(|(rule, re)| {
+                rule.field
+                    .values(album)
+                    .into_iter()
+                    .find(|v| re.is_match(v))
+                    .map(|v| RuleMatch {
+                        rule,
+                        value: v.to_string(),
+                    })
+            })
     }
 }
 
@@ -221,9 +488,20 @@ mod tests {
 
     #[test]
     fn blacklist() {
-        let black_artists = vec!["donald duck", "agh[0o]ri tantrik"];
-        let black_labels = vec!["sony", "sonic tantra .*", "supraphon"];
-        let blacklist = Blacklist::new(black_artists, black_labels).unwrap();
+        let black_artists = ["donald duck", "agh[0o]ri tantrik"];
+        let black_labels = ["sony", "sonic tantra .*", "supraphon"];
+        let blacklist = Blacklist::new(
+            black_artists
+                .iter()
+                .map(|p| BlacklistRule::deny(BlacklistField::Artist, p))
+                .chain(
+                    black_labels
+                        .iter()
+                        .map(|p| BlacklistRule::deny(BlacklistField::Label, p)),
+                )
+                .collect(),
+        )
+        .unwrap();
 
         let album = Album {
             url: "https://ektoplazm.com/free-music/haltya-japan-anime-punk-sessions-ep".to_string(),
@@ -240,6 +518,7 @@ mod tests {
                     bpm: None,
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
                 Track {
@@ -248,12 +527,18 @@ mod tests {
                     bpm: None,
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
             ],
             youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
         };
-        assert!(!blacklist.matches(&album));
+        assert!(!matches!(blacklist.explain(&album), Verdict::Denied(_)));
 
         let album = Album {
             url: "https://ektoplazm.com/free-music/sonic-shamans-vol-ii".to_string(),
@@ -270,6 +555,7 @@ mod tests {
                     bpm: Some(150),
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
                 Track {
@@ -278,12 +564,18 @@ mod tests {
                     bpm: Some(150),
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
             ],
             youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
         };
-        assert!(blacklist.matches(&album));
+        assert!(matches!(blacklist.explain(&album), Verdict::Denied(_)));
 
         let album = Album {
             url: "https://ektoplazm.com/free-music/high-tech-mechanica".to_string(),
@@ -300,6 +592,7 @@ mod tests {
                     bpm: Some(175),
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
                 Track {
@@ -308,11 +601,173 @@ mod tests {
                     bpm: Some(165),
                     mp3_file: None,
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
             ],
             youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
         };
-        assert!(blacklist.matches(&album));
+        assert!(matches!(blacklist.explain(&album), Verdict::Denied(_)));
+        let artist_rule = BlacklistRule::deny(BlacklistField::Artist, "agh[0o]ri tantrik");
+        assert_eq!(
+            blacklist.explain(&album),
+            Verdict::Denied(RuleMatch {
+                rule: &artist_rule,
+                value: "Aghori Tantrik".to_string(),
+            })
+        );
+
+        let rules = vec![
+            BlacklistRule::deny(BlacklistField::Tag, "psycore"),
+            BlacklistRule::deny(BlacklistField::AlbumTitle, ".*mechanica"),
+            BlacklistRule::deny(BlacklistField::Url, "https://example.com/.*"),
+            BlacklistRule::allow(BlacklistField::Artist, "murukhan|goch"),
+        ];
+        let blacklist = Blacklist::new(rules.clone()).unwrap();
+        assert_eq!(
+            blacklist.explain(&album),
+            Verdict::Allowed {
+                allow: RuleMatch {
+                    rule: &rules[3],
+                    value: "Murukhan".to_string(),
+                },
+                deny: RuleMatch {
+                    rule: &rules[0],
+                    value: "Psycore".to_string(),
+                },
+            }
+        );
+        assert!(!matches!(blacklist.explain(&album), Verdict::Denied(_)));
+
+        let album = Album {
+            url: "https://example.com/some-album".to_string(),
+            title: "High Tech Mechanica".to_string(),
+            tags: vec![],
+            tracks: vec![],
+            ..album
+        };
+        assert_eq!(
+            blacklist.explain(&album),
+            Verdict::Denied(RuleMatch {
+                rule: &rules[1],
+                value: "High Tech Mechanica".to_string(),
+            })
+        );
+        let album = Album {
+            title: "Something Else".to_string(),
+            ..album
+        };
+        assert!(matches!(blacklist.explain(&album), Verdict::Denied(_)));
+        let album = Album {
+            url: "https://ektoplazm.com/free-music/x".to_string(),
+            ..album
+        };
+        assert_eq!(blacklist.explain(&album), Verdict::Clean);
+        assert!(Blacklist::new(vec![BlacklistRule::deny(BlacklistField::Tag, "(")]).is_err());
+    }
+
+    #[test]
+    fn merge_refetched() {
+        let track = |title: &str, yt: Option<&str>| Track {
+            artist: "Globular".to_string(),
+            title: title.to_string(),
+            bpm: None,
+            mp3_file: Some(PathBuf::from(format!("{}.mp3", title))),
+            video_file: yt.map(|_| PathBuf::from(format!("{}.avi", title))),
+            profile: None,
+            youtube_id: yt.map(|y| youtube::VideoID(y.to_string())),
+        };
+        let album = |tracks: Vec<Track>, yt: Option<&str>| Album {
+            url: "https://ektoplazm.com/free-music/globular-entangled-everything".to_string(),
+            artist: Some("Globular".to_string()),
+            title: "Entangled Everything".to_string(),
+            license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+            year: Some(2018),
+            labels: vec![],
+            tags: vec!["Downtempo".to_string(), "Psy Dub".to_string()],
+            tracks,
+            youtube_id: yt.map(|y| youtube::PlaylistID(y.to_string())),
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+
+        let mut stored = album(
+            vec![
+                track("Popping Out", Some("aaa")),
+                track("For The Time Being", Some("bbb")),
+                track("The Chalice", None),
+            ],
+            Some("PL0123"),
+        );
+        let fresh = album(
+            vec![
+                track("Popping Out", None),
+                track("For The Time Being", None),
+                track("The Chalice", None),
+            ],
+            None,
+        );
+        assert_eq!(
+            stored.merge_refetched(fresh.clone()).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            stored.youtube_id,
+            Some(youtube::PlaylistID("PL0123".to_string()))
+        );
+        assert_eq!(
+            stored.tracks[1].youtube_id,
+            Some(youtube::VideoID("bbb".to_string()))
+        );
+        assert_eq!(
+            stored.tracks[1].video_file,
+            Some(PathBuf::from("For The Time Being.avi"))
+        );
+        assert_eq!(stored.tracks[2].youtube_id, None);
+
+        let fresh = album(
+            vec![
+                track("for the time being", None),
+                track("Popping Out", None),
+                track("The Chalice (Edit)", None),
+                track("Dasein", None),
+            ],
+            None,
+        );
+        let diff = stored.merge_refetched(fresh).unwrap();
+        assert_eq!(diff.len(), 4);
+        assert_eq!(
+            stored.tracks[0].youtube_id,
+            Some(youtube::VideoID("bbb".to_string()))
+        );
+        assert_eq!(
+            stored.tracks[1].youtube_id,
+            Some(youtube::VideoID("aaa".to_string()))
+        );
+        assert_eq!(stored.tracks[2].title, "The Chalice (Edit)");
+        assert_eq!(stored.tracks[3].youtube_id, None);
+
+        let fresh = album(vec![track("Popping Out", None)], None);
+        assert!(stored.merge_refetched(fresh).is_err());
+        assert_eq!(stored.tracks.len(), 4);
+
+        let mut fresh = album(stored.tracks.clone(), None);
+        fresh.title = "Entangled Everything (Remastered)".to_string();
+        assert_eq!(
+            stored.merge_refetched(fresh).unwrap(),
+            vec![
+                "album renamed from \"Globular - Entangled Everything\" to \
+                 \"Globular - Entangled Everything (Remastered)\""
+            ]
+        );
+        assert_eq!(stored.title, "Entangled Everything (Remastered)");
     }
 }
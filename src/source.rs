@@ -1,8 +1,8 @@
 use crate::model::*;
+use crate::template::{self, TemplateKind};
 use crate::util;
 
 use std::collections::VecDeque;
-use std::fmt::Write;
 use std::io::{copy, Read, Seek};
 use std::path::{Path, PathBuf};
 use std::vec::Vec;
@@ -11,116 +11,156 @@ use hyper;
 use hyper_rustls;
 use id3;
 use log;
+use regex::Regex;
 use select::document::Document;
-use select::predicate::{Class, Name, Predicate};
+use select::predicate::{Attr, Class, Name, Predicate};
 use tempfile;
 use zip;
 
+mod archiveorg;
+mod bandcamp;
+mod local;
+
+pub use archiveorg::ArchiveOrgScraper;
+pub use local::path_to_url;
+
 pub trait Source {
+    // Subdirectory of the templates directory with the source specific templates.
+    fn name(&self) -> &'static str;
     fn belongs(&self, url: &str) -> bool;
-    fn fetch(&self, url: &str, mp3_dir: &Path) -> Result<Album, util::Error>;
-    fn description(&self, album: &Album, track: &Track) -> Result<String, util::Error>;
+    fn fetch(&self, url: &str, mp3_dir: &Path, format: AudioFormat) -> Result<Album, util::Error>;
+
+    // Used unless the user provides a template.
+    fn default_template(&self, kind: TemplateKind) -> String {
+        kind.default_source().to_string()
+    }
 }
 
-const SOURCES: [&dyn Source; 1] = [&Ektoplazm {}];
+const SOURCES: [&dyn Source; 4] = [
+    &Ektoplazm {},
+    &bandcamp::Bandcamp {},
+    &archiveorg::ArchiveOrg {},
+    &local::Local {},
+];
 
-pub fn fetch(url: &str, mp3_dir: &Path) -> Result<Album, util::Error> {
+pub fn fetch(url: &str, mp3_dir: &Path, format: AudioFormat) -> Result<Album, util::Error> {
     for s in &SOURCES {
         if s.belongs(url) {
-            return s.fetch(url, mp3_dir);
+            return s.fetch(url, mp3_dir, format);
         }
     }
 
     Err(util::Error::new(&format!("No source known for {}", url)))
 }
 
-pub fn description(album: &Album, track: &Track) -> Result<String, util::Error> {
-    for s in &SOURCES {
-        if s.belongs(&album.url) {
-            return s.description(album, track);
+// Renders template for the album, or its track at index, with user templates from dir taking
+// precedence over the defaults of the album's source.
+pub fn render_template(
+    dir: &Path,
+    kind: TemplateKind,
+    album: &Album,
+    track: Option<usize>,
+) -> Result<String, util::Error> {
+    render_template_context(dir, kind, album, &template::context(album, track))
+}
+
+pub fn render_template_context(
+    dir: &Path,
+    kind: TemplateKind,
+    album: &Album,
+    context: &template::Value,
+) -> Result<String, util::Error> {
+    let source = match SOURCES.iter().find(|s| s.belongs(&album.url)) {
+        Some(s) => s,
+        None => {
+            return Err(util::Error::new(&format!(
+                "No source known for {}",
+                album.url
+            )))
         }
-    }
+    };
+    let tmpl = match template::load(dir, source.name(), kind)? {
+        Some(t) => t,
+        None => template::Template::parse(&source.default_template(kind))?,
+    };
 
-    Err(util::Error::new(&format!(
-        "No source known for {}",
-        album.url
-    )))
+    Ok(tmpl.render(context).trim().to_string())
+}
+
+// Descriptions with links to the album page and the download on top.
+fn download_link_template(kind: TemplateKind, site: &str) -> String {
+    match kind {
+        TemplateKind::VideoDescription => format!(
+            "Download the full album from {}: {{{{album.url}}}}\n\n{}",
+            site,
+            kind.default_source()
+        ),
+        TemplateKind::PlaylistDescription | TemplateKind::AlbumVideoDescription => format!(
+            "Album page on {}: {{{{album.url}}}}\n\
+             {{{{#album.download_url}}}}\n\
+             Free download: {{{{album.download_url}}}}\n\
+             {{{{/album.download_url}}}}\n\
+             \n{}",
+            site,
+            kind.default_source()
+        ),
+        _ => kind.default_source().to_string(),
+    }
 }
 
 struct Ektoplazm {}
 
 impl Source for Ektoplazm {
+    fn name(&self) -> &'static str {
+        "ektoplazm"
+    }
+
     fn belongs(&self, url: &str) -> bool {
         url.starts_with("https://ektoplazm.com/free-music/")
     }
 
-    fn fetch(&self, url: &str, mp3_dir: &Path) -> Result<Album, util::Error> {
+    fn fetch(&self, url: &str, mp3_dir: &Path, format: AudioFormat) -> Result<Album, util::Error> {
         log::info!("Fetching {}", url);
         let res = download(url)?;
-        let (mp3_link, license_link, labels, tags, _) = ektoplazm_parse(res)?;
-        let mut res = download(&mp3_link)?;
-
-        let mut tmp = tempfile::tempfile()?;
-        copy(&mut res, &mut tmp)?;
-
-        let tmpdir = unpack(tmp, mp3_dir)?;
-        let (album_title, album_artist, album_year, tracks) = read_id3(tmpdir.path())?;
+        let page = ektoplazm_parse(res)?;
+        let link = match (format, page.flac_link) {
+            (AudioFormat::Flac, Some(l)) => l,
+            (AudioFormat::Flac, None) => {
+                log::warn!("No FLAC download, using MP3");
+                page.mp3_link
+            }
+            (AudioFormat::Mp3, _) => page.mp3_link,
+        };
+        let tmpdir = download_unpack(&link, mp3_dir)?;
+        let (album_title, album_artist, album_year, mut tracks) = read_tags(tmpdir.path())?;
+        let notes = crosscheck_tracklist(&page.tracks, &mut tracks)?;
+        for note in &notes {
+            log::warn!("{}", note);
+        }
         let album = Album {
             url: url.to_string(),
 
             artist: album_artist,
-            title: album_title,
-            license: license_link,
+            title: album_title.ok_or(NO_ALBUM_TITLE)?,
+            license: page.license,
             year: album_year,
-            labels: labels,
-            tags: tags,
+            labels: page.labels,
+            tags: page.tags,
             tracks: tracks,
             youtube_id: None,
+            release_date: page.release_date,
+            description: page.description,
+            download_url: Some(link),
+            album_video_id: None,
+            notes,
         };
 
-        let tmpdir = tmpdir.into_path();
-        if let Err(e) = std::fs::rename(&tmpdir, album.dirname(mp3_dir)) {
-            std::fs::remove_dir_all(tmpdir)?;
-            return Err(util::Error::from(e));
-        }
-
+        move_album(tmpdir, &album, mp3_dir)?;
         Ok(album)
     }
 
-    fn description(&self, album: &Album, track: &Track) -> Result<String, util::Error> {
-        let mut trackno = 99;
-        for (n, t) in album.tracks.iter().enumerate() {
-            if track == t {
-                trackno = n + 1;
-                break;
-            }
-        }
-
-        let mut result = format!("Download the full album from Ektoplazm: {}\n", album.url);
-
-        write!(result, "\nArtist: {}", track.artist)?;
-        write!(result, "\nTrack: {}", track.title)?;
-        if let Some(y) = album.year {
-            write!(result, "\nAlbum: {} ({})", album.title, y)?;
-        } else {
-            write!(result, "\nAlbum: {}", album.title)?;
-        }
-        write!(result, "\nTrack number: {:02}", trackno)?;
-        if let Some(b) = track.bpm {
-            write!(result, "\nBPM: {}", b)?;
-        }
-        write!(result, "\n")?;
-        if !album.tags.is_empty() {
-            write!(result, "\nTags: {}", album.tags.join(", "))?;
-        }
-        if !album.labels.is_empty() {
-            write!(result, "\nReleased by: {}", album.labels.join(" & "))?;
-        }
-        if let Some(l) = &album.license {
-            write!(result, "\nLicense: {}", l)?;
-        }
-
-        Ok(result)
+    fn default_template(&self, kind: TemplateKind) -> String {
+        download_link_template(kind, "Ektoplazm")
     }
 }
 
@@ -147,7 +187,68 @@ fn required_tag<T>(result: Option<T>, what: &str, path: &Path) -> Result<T, util
     )))
 }
 
-fn read_id3(dir: &Path) -> Result<(String, Option<String>, Option<u16>, Vec<Track>), util::Error> {
+// Tags of a single audio file, regardless of the tag format.
+#[derive(Default)]
+struct FileTags {
+    track: Option<u32>,
+    artist: Option<String>,
+    title: Option<String>,
+    bpm: Option<u16>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    year: Option<u16>,
+}
+
+fn read_id3(path: &Path) -> Result<FileTags, util::Error> {
+    let tag = id3::Tag::read_from_path(path)?;
+    Ok(FileTags {
+        track: tag.track(),
+        artist: tag.artist().map(String::from),
+        title: tag.title().map(String::from),
+        bpm: tag
+            .get("TBPM")
+            .and_then(|f| f.content().text())
+            .and_then(|t| t.parse().ok()),
+        album_artist: tag.album_artist().map(String::from),
+        album: tag.album().map(String::from),
+        year: tag.year().map(|y| y as u16),
+    })
+}
+
+fn read_vorbis(path: &Path) -> Result<FileTags, util::Error> {
+    let reader = claxon::FlacReader::open_ext(
+        path,
+        claxon::FlacReaderOptions {
+            metadata_only: true,
+            read_vorbis_comment: true,
+        },
+    )?;
+    let get = |name: &str| reader.get_tag(name).next().map(String::from);
+    // numeric fields can look like "3/10" or "2018-05-01"
+    let leading_num = |name: &str| {
+        get(name).and_then(|v| {
+            v.split(|c: char| !c.is_ascii_digit())
+                .next()
+                .and_then(|n| n.parse::<u32>().ok())
+        })
+    };
+
+    Ok(FileTags {
+        track: leading_num("TRACKNUMBER"),
+        artist: get("ARTIST"),
+        title: get("TITLE"),
+        bpm: leading_num("BPM").map(|b| b as u16),
+        album_artist: get("ALBUMARTIST").or_else(|| get("ALBUM ARTIST")),
+        album: get("ALBUM"),
+        year: leading_num("DATE").map(|y| y as u16),
+    })
+}
+
+// Reads tags of all MP3 (ID3) and FLAC (Vorbis comments) files in the directory.
+// Album title is None when the tags don't have it.
+fn read_tags(
+    dir: &Path,
+) -> Result<(Option<String>, Option<String>, Option<u16>, Vec<Track>), util::Error> {
     let mut tracks: Vec<(u32, Track)> = Vec::new();
     let mut album_artist: Option<String> = None;
     let mut album_title: Option<String> = None;
@@ -160,35 +261,29 @@ fn read_id3(dir: &Path) -> Result<(String, Option<String>, Option<u16>, Vec<Trac
         }
         let path = f.path();
 
-        match path.extension() {
-            None => continue,
-            Some(ext) => {
-                if ext != "mp3" {
-                    continue;
-                }
-            }
-        }
+        let tag = match path.extension().and_then(|e| e.to_str()) {
+            Some("mp3") => read_id3(&path)?,
+            Some("flac") => read_vorbis(&path)?,
+            _ => continue,
+        };
 
-        let tag = id3::Tag::read_from_path(&path)?;
-        let num = required_tag(tag.track(), "Track number", &path)?;
+        let num = required_tag(tag.track, "Track number", &path)?;
         tracks.push((
             num,
             Track {
-                artist: required_tag(tag.artist(), "Artist", &path)?.to_string(),
-                title: required_tag(tag.title(), "Title", &path)?.to_string(),
-                bpm: tag
-                    .get("TBPM")
-                    .and_then(|f| f.content().text())
-                    .and_then(|t| t.parse().ok()),
+                artist: required_tag(tag.artist, "Artist", &path)?,
+                title: required_tag(tag.title, "Title", &path)?,
+                bpm: tag.bpm,
                 mp3_file: Some(PathBuf::from(f.file_name())),
                 video_file: None,
+                profile: None,
                 youtube_id: None,
             },
         ));
 
-        track_to_album(&mut album_artist, tag.album_artist());
-        track_to_album(&mut album_title, tag.album());
-        album_year = tag.year().map(|y| y as u16);
+        track_to_album(&mut album_artist, tag.album_artist.as_deref());
+        track_to_album(&mut album_title, tag.album.as_deref());
+        album_year = tag.year;
     }
 
     tracks.sort_by_key(|t| t.0);
@@ -198,13 +293,20 @@ fn read_id3(dir: &Path) -> Result<(String, Option<String>, Option<u16>, Vec<Trac
         album_artist = None;
     }
 
-    match album_title {
-        None => Err(util::Error::new("Album artist cannot be determined")),
-        Some(t) => Ok((t, album_artist, album_year, tracks)),
-    }
+    Ok((album_title, album_artist, album_year, tracks))
 }
 
+const NO_ALBUM_TITLE: &str = "Album title cannot be determined";
+
 fn download(url: &str) -> Result<hyper::client::response::Response, util::Error> {
+    download_opt(url)?.ok_or_else(|| {
+        log::error!("Failed to GET {}: not found", url);
+        util::Error::new("Failed to fetch URL")
+    })
+}
+
+// Like download but returns None on 404.
+fn download_opt(url: &str) -> Result<Option<hyper::client::response::Response>, util::Error> {
     log::debug!("GET {}", url);
     let client = hyper::Client::with_connector(hyper::net::HttpsConnector::new(
         hyper_rustls::TlsClient::new(),
@@ -214,32 +316,65 @@ fn download(url: &str) -> Result<hyper::client::response::Response, util::Error>
         .header(hyper::header::UserAgent(util::USER_AGENT.to_owned()))
         .send()?;
 
+    if res.status == hyper::status::StatusCode::NotFound {
+        log::debug!("Not found: {}", url);
+        return Ok(None);
+    }
     //let mut body = Vec::new();
     if res.status != hyper::status::StatusCode::Ok {
         log::error!("Failed to GET {}: {:?}", url, res);
         //let _body_len = res.read_to_end(&mut body)?;
         //log::debug!("{:?}\n{:?}", res, std::str::from_utf8(&body).unwrap());
-        return Err(util::Error::new("Failed to fetch URL"));
+        let err = util::Error::new("Failed to fetch URL");
+        return Err(if res.status.is_server_error() {
+            err.retriable()
+        } else {
+            err
+        });
     }
     log::debug!("Got status {}", res.status);
 
-    Ok(res)
+    Ok(Some(res))
+}
+
+#[derive(Debug, PartialEq)]
+struct EktoplazmPage {
+    mp3_link: String,
+    flac_link: Option<String>,
+    wav_link: Option<String>,
+    license: Option<String>,
+    labels: Vec<String>,
+    tags: Vec<String>,
+    release_date: Option<chrono::NaiveDate>,
+    description: Option<String>,
+    rating: Option<f32>, // out of 5
+    votes: Option<u32>,
+    downloads: Option<u32>,
+    cover: Option<String>,
+    tracks: Vec<WebTrack>,
+}
+
+#[derive(Debug, PartialEq)]
+struct WebTrack {
+    num: u32,
+    artist: Option<String>, // only on VA releases
+    title: String,          // includes remix, e.g. "Gnome Alone (Solarfall Remix)"
+    bpm: Option<u16>,       // first one if the tempo changes, "(123/246 BPM)"
 }
 
-fn ektoplazm_parse<T: Read>(
-    res: T,
-) -> util::Result<(String, Option<String>, Vec<String>, Vec<String>, u32)> {
+fn ektoplazm_parse<T: Read>(res: T) -> util::Result<EktoplazmPage> {
     let doc = Document::from_read(res)?;
 
-    let mp3_link = match doc
-        .find(Class("entry").descendant(Name("a")))
-        .filter(|tag| tag.text() == "MP3 Download")
-        .filter_map(|tag| tag.attr("href"))
-        .next()
-    {
-        None => return Err(util::Error::new("Failed to find download link")),
-        Some(link) => link.to_string(),
+    let download_link = |text: &str| {
+        doc.find(Class("entry").descendant(Name("a")))
+            .filter(|tag| tag.text() == text)
+            .filter_map(|tag| tag.attr("href"))
+            .next()
+            .map(|x| x.to_string())
     };
+    let mp3_link = download_link("MP3 Download").ok_or("Failed to find download link")?;
+    let flac_link = download_link("FLAC Download");
+    let wav_link = download_link("WAV Download");
 
     let license_link = doc
         .find(Class("entry").descendant(Name("a")))
@@ -265,9 +400,262 @@ fn ektoplazm_parse<T: Read>(
         .map(|tag| tag.text())
         .collect();
 
-    let tracknum = doc.find(Class("tl").descendant(Class("t"))).count();
+    let release_date = doc
+        .find(Class("post").child(Name("h3")).child(Class("d")))
+        .next()
+        .and_then(|tag| chrono::NaiveDate::parse_from_str(&tag.text(), "%B %d, %Y").ok());
+
+    let itemprop = |name: &str| {
+        doc.find(Attr("itemprop", name))
+            .filter_map(|tag| tag.attr("content"))
+            .next()
+            .map(|x| x.to_string())
+    };
+    let description = itemprop("description");
+    let rating = itemprop("ratingValue").and_then(|r| r.parse().ok());
+    let votes = itemprop("ratingCount").and_then(|v| v.parse().ok());
+
+    let downloads = doc
+        .find(Class("dc").descendant(Name("strong")))
+        .next()
+        .and_then(|tag| tag.text().replace(",", "").parse().ok());
+
+    let cover = doc
+        .find(Class("entry").descendant(Class("cover")))
+        .filter_map(|tag| tag.attr("src"))
+        .next()
+        .map(|x| x.to_string());
+
+    let tracks = doc
+        .find(Class("tl"))
+        .next()
+        .map(|tl| ektoplazm_parse_tracklist(&tl))
+        .unwrap_or_default();
+
+    Ok(EktoplazmPage {
+        mp3_link,
+        flac_link,
+        wav_link,
+        license: license_link,
+        labels,
+        tags,
+        release_date,
+        description,
+        rating,
+        votes,
+        downloads,
+        cover,
+        tracks,
+    })
+}
+
+// Compares the tracklist on the web page with the tags of the downloaded files. If only the
+// track numbers in the tags are off, the tracks are put into the web order, missing BPM is taken
+// from the web. Returns the tolerated differences, fails when the two lists don't describe the
+// same tracks.
+fn crosscheck_tracklist(web: &[WebTrack], tracks: &mut Vec<Track>) -> util::Result<Vec<String>> {
+    if web.is_empty() {
+        return Ok(vec!["No tracklist on the web page".to_string()]);
+    }
+    let mismatch = |tracks: &[Track]| {
+        util::Error::new(&format!(
+            "Tracklist on the web does not match the files:\n{}",
+            tracklist_diff(web, tracks)
+        ))
+    };
+    if web.len() != tracks.len() {
+        return Err(mismatch(tracks));
+    }
+
+    let mut notes = Vec::new();
+    let in_order = web
+        .iter()
+        .zip(tracks.iter())
+        .all(|(w, t)| similar_titles(&w.title, &t.title));
+    if !in_order {
+        let mut order: Vec<usize> = Vec::new();
+        for w in web {
+            match (0..tracks.len())
+                .find(|j| !order.contains(j) && similar_titles(&w.title, &tracks[*j].title))
+            {
+                Some(j) => order.push(j),
+                None => return Err(mismatch(tracks)),
+            }
+        }
+        for (i, &j) in order.iter().enumerate() {
+            if i != j {
+                notes.push(format!(
+                    "track {:02} {:?} is tagged as track {:02}",
+                    i + 1,
+                    tracks[j].title,
+                    j + 1
+                ));
+            }
+        }
+        let mut old: Vec<Option<Track>> = tracks.drain(..).map(Some).collect();
+        tracks.extend(order.into_iter().filter_map(|j| old[j].take()));
+    }
+
+    for (i, (w, t)) in web.iter().zip(tracks.iter_mut()).enumerate() {
+        if normalize_title(&w.title) != normalize_title(&t.title) {
+            notes.push(format!(
+                "track {:02} is {:?} on the web, {:?} in tags",
+                i + 1,
+                w.title,
+                t.title
+            ));
+        }
+        match (w.bpm, t.bpm) {
+            (Some(wb), None) => {
+                notes.push(format!("track {:02} BPM {} taken from the web", i + 1, wb));
+                t.bpm = Some(wb);
+            }
+            // half or double tempo is the same thing
+            // u32 because garbage tags can be anything up to u16::MAX
+            (Some(wb), Some(tb))
+                if ![u32::from(tb), u32::from(tb) * 2, u32::from(tb) / 2]
+                    .iter()
+                    .any(|b| (i64::from(*b) - i64::from(wb)).abs() <= 1) =>
+            {
+                notes.push(format!(
+                    "track {:02} BPM is {} on the web, {} in tags",
+                    i + 1,
+                    wb,
+                    tb
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(notes)
+}
+
+// One line per track: ' ' same, '~' similar, '!' different title, '+' only on the web,
+// '-' only in the files.
+fn tracklist_diff(web: &[WebTrack], tracks: &[Track]) -> String {
+    let mut res = String::new();
+    for i in 0..std::cmp::max(web.len(), tracks.len()) {
+        let line = match (web.get(i), tracks.get(i)) {
+            (Some(w), Some(t)) if normalize_title(&w.title) == normalize_title(&t.title) => {
+                format!("  {:02} {}", i + 1, w.title)
+            }
+            (Some(w), Some(t)) if similar_titles(&w.title, &t.title) => {
+                format!("~ {:02} {} / {}", i + 1, w.title, t.title)
+            }
+            (Some(w), Some(t)) => format!("! {:02} {} / {}", i + 1, w.title, t.title),
+            (Some(w), None) => format!("+ {:02} {}", i + 1, w.title),
+            (None, Some(t)) => format!("- {:02} {}", i + 1, t.title),
+            (None, None) => unreachable!(),
+        };
+        res.push_str(&line);
+        res.push('\n');
+    }
+    res
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Tolerates typos, different punctuation and remix name present only on one side.
+fn similar_titles(a: &str, b: &str) -> bool {
+    let (a, b) = (normalize_title(a), normalize_title(b));
+    let (short, long) = if a.len() < b.len() {
+        (&a, &b)
+    } else {
+        (&b, &a)
+    };
+    if short.len() >= 4 && long.starts_with(short.as_str()) {
+        return true;
+    }
+    edit_distance(&a, &b) * 5 <= long.chars().count()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(std::cmp::min(subst, std::cmp::min(prev[j + 1], cur[j]) + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
 
-    Ok((mp3_link, license_link, labels, tags, tracknum as u32))
+// The tracklist is a flat sequence of spans separated by <br/>:
+// <span class="n">01</span> - <span class="a">Artist</span> - <span class="t">Title</span>
+// <span class="r">(Remix)</span> <span class="d">(126 BPM)</span>
+fn ektoplazm_parse_tracklist(tl: &select::node::Node) -> Vec<WebTrack> {
+    lazy_static! {
+        static ref BPM: Regex = Regex::new(r"^\((\d+)[^)]*BPM\)$").unwrap();
+    }
+
+    let mut tracks: Vec<WebTrack> = Vec::new();
+    for span in tl.children().filter(|n| n.name() == Some("span")) {
+        let text = span.text().trim().to_string();
+        match span.attr("class") {
+            Some("n") => tracks.push(WebTrack {
+                num: text.parse().unwrap_or(tracks.len() as u32 + 1),
+                artist: None,
+                title: String::new(),
+                bpm: None,
+            }),
+            Some(class) => {
+                let track = match tracks.last_mut() {
+                    Some(t) => t,
+                    None => continue,
+                };
+                match class {
+                    "a" => track.artist = Some(text),
+                    "t" => track.title = text,
+                    "r" => {
+                        track.title.push(' ');
+                        track.title.push_str(&text);
+                    }
+                    "d" => {
+                        track.bpm = BPM
+                            .captures(&text)
+                            .and_then(|c| c.get(1))
+                            .and_then(|m| m.as_str().parse().ok())
+                    }
+                    _ => {}
+                }
+            }
+            None => {}
+        }
+    }
+    tracks
+}
+
+// Downloads ZIP archive and unpacks it into temporary directory inside mp3_dir.
+fn download_unpack(url: &str, mp3_dir: &Path) -> Result<tempfile::TempDir, util::Error> {
+    let mut res = download(url)?;
+
+    let mut tmp = tempfile::tempfile()?;
+    copy(&mut res, &mut tmp)?;
+
+    unpack(tmp, mp3_dir)
+}
+
+// Gives the unpacked album its final name in mp3_dir.
+fn move_album(tmpdir: tempfile::TempDir, album: &Album, mp3_dir: &Path) -> Result<(), util::Error> {
+    let tmpdir = tmpdir.into_path();
+    if let Err(e) = std::fs::rename(&tmpdir, album.dirname(mp3_dir)) {
+        std::fs::remove_dir_all(tmpdir)?;
+        return Err(util::Error::from(e));
+    }
+    Ok(())
 }
 
 fn unpack<T: Read + Seek>(res: T, outdir: &Path) -> Result<tempfile::TempDir, util::Error> {
@@ -299,14 +687,19 @@ fn unpack<T: Read + Seek>(res: T, outdir: &Path) -> Result<tempfile::TempDir, ut
     Ok(tmpdir)
 }
 
+pub const EKTOPLAZM_FREE_MUSIC: &str = "https://ektoplazm.com/section/free-music";
+
+// Walks any of the listing pages - free music section, style, label or artist archive.
 pub struct EktoplazmScraper {
+    listing: String,
     next_page: u32,
     urls: VecDeque<String>,
 }
 
 impl EktoplazmScraper {
-    pub fn from_offset(offset: u32) -> EktoplazmScraper {
+    pub fn new(listing: &str, offset: u32) -> EktoplazmScraper {
         EktoplazmScraper {
+            listing: listing.trim_end_matches('/').to_string(),
             next_page: 1 + offset / 5,
             urls: VecDeque::new(),
         }
@@ -318,7 +711,7 @@ impl std::iter::Iterator for EktoplazmScraper {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.urls.is_empty() {
-            self.urls = match ektoplazm_scrape_list(self.next_page) {
+            self.urls = match ektoplazm_scrape_list(&self.listing, self.next_page) {
                 Err(e) => return Some(Err(e)),
                 Ok(urls) => urls,
             };
@@ -337,9 +730,28 @@ impl std::iter::Iterator for EktoplazmScraper {
     }
 }
 
-fn ektoplazm_scrape_list(offset: u32) -> Result<VecDeque<String>, util::Error> {
-    let url = format!("https://ektoplazm.com/section/free-music/page/{}", offset);
-    let res = download(&url)?;
+// Listing URL for the selector, kind is one of style, label, artist. Name can be given either
+// as displayed ("Psy Dub") or as in the URL ("psy-dub").
+pub fn ektoplazm_listing(kind: &str, name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("https://ektoplazm.com/{}/{}", kind, slug)
+}
+
+fn ektoplazm_scrape_list(listing: &str, page: u32) -> Result<VecDeque<String>, util::Error> {
+    let url = format!("{}/page/{}", listing, page);
+    match download_opt(&url)? {
+        // past the last page
+        None => Ok(VecDeque::new()),
+        Some(res) => ektoplazm_parse_list(res),
+    }
+}
+
+fn ektoplazm_parse_list<T: Read>(res: T) -> Result<VecDeque<String>, util::Error> {
     let doc = Document::from_read(res)?;
 
     let links = doc
@@ -354,13 +766,13 @@ fn ektoplazm_scrape_list(offset: u32) -> Result<VecDeque<String>, util::Error> {
 /*
 fn ektoplazm_scrape_album(url: &str) -> Result<(u32, u32), util::Error> {
     let res = download(url)?;
-    let (mp3_link, _, _, _, _tracks) = ektoplazm_parse(res)?;
+    let page = ektoplazm_parse(res)?;
 
-    let res = download(&mp3_link)?;
+    let res = download(&page.mp3_link)?;
     let length = res.headers.get::<hyper::header::ContentLength>().map(|n| n.0 as u32).unwrap_or(0);
     drop(res);
 
-    Ok((tracks, length))
+    Ok((page.tracks.len() as u32, length))
 }
 */
 
@@ -371,7 +783,7 @@ mod tests {
     use std::fs;
     use std::path::PathBuf;
 
-    fn fixture(fname: &str) -> std::fs::File {
+    pub(super) fn fixture(fname: &str) -> std::fs::File {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests");
         d.push("data");
@@ -382,65 +794,271 @@ mod tests {
 
     #[test]
     fn parse_ektoplazm() {
-        let cases = &[
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let files = |f: &str| format!("https://ektoplazm.com/files/{}", f.replace(" ", "%20"));
+        let img = |f: &str| format!("https://ektoplazm.com/img/{}", f);
+        let track = |num: u32, artist: Option<&str>, title: &str, bpm: Option<u16>| WebTrack {
+            num,
+            artist: artist.map(String::from),
+            title: title.to_string(),
+            bpm,
+        };
+
+        let cases = vec![
             (
                 "ektoplazm1.html",
-                "https://ektoplazm.com/files/Globular%20-%20Entangled%20Everything%20-%202018%20-%20MP3.zip",
-                Some("https://creativecommons.org/licenses/by-nc-sa/4.0/"),
-                Vec::new(),
-                vec!["Downtempo", "Psy Dub"],
-                10,
+                EktoplazmPage {
+                    mp3_link: files("Globular%20-%20Entangled%20Everything%20-%202018 - MP3.zip"),
+                    flac_link: Some(files("Globular%20-%20Entangled%20Everything%20-%202018 - FLAC.zip")),
+                    wav_link: Some(files("Globular%20-%20Entangled%20Everything%20-%202018 - WAV.rar")),
+                    license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+                    labels: strings(&[]),
+                    tags: strings(&["Downtempo", "Psy Dub"]),
+                    release_date: Some(chrono::NaiveDate::from_ymd(2018, 12, 29)),
+                    description: Some("An eclectic ride through psychedelic downtempo and dub.".to_string()),
+                    rating: Some(4.56),
+                    votes: Some(95),
+                    downloads: Some(44_302),
+                    cover: Some(img("globular-entangled-everything-300x300.jpg")),
+                    tracks: vec![
+                    track(1, None, "Popping Out", None),
+                    track(2, None, "For The Time Being", Some(65)),
+                    track(3, None, "The Chalice", Some(95)),
+                    track(4, None, "Total Perspective Vortex", Some(58)),
+                    track(5, None, "One Step Beyond", Some(77)),
+                    track(6, None, "Dasein", Some(69)),
+                    track(7, None, "Be Patient Towards What Is Unsolved In Your Heart", Some(100)),
+                    track(8, None, "Dreamland Overflow", Some(74)),
+                    track(9, None, "Kaleidoscope Tribe", Some(74)),
+                    track(10, None, "This Strange Attractor", Some(67)),
+                    ],
+                },
             ),
             (
                 "ektoplazm-label.html",
-                "https://ektoplazm.com/files/White%20Morph%20-%20Dream%20Catcher%20-%202012%20-%20MP3.zip",
-                Some("https://creativecommons.org/licenses/by-nc-sa/3.0/"),
-                vec!["3L3Mental Records"],
-                vec!["Full-On", "Morning"],
-                4,
+                EktoplazmPage {
+                    mp3_link: files("White%20Morph%20-%20Dream%20Catcher%20-%202012 - MP3.zip"),
+                    flac_link: Some(files("White%20Morph%20-%20Dream%20Catcher%20-%202012 - FLAC.zip")),
+                    wav_link: Some(files("White%20Morph%20-%20Dream%20Catcher%20-%202012 - WAV.rar")),
+                    license: Some("https://creativecommons.org/licenses/by-nc-sa/3.0/".to_string()),
+                    labels: strings(&["3L3Mental Records"]),
+                    tags: strings(&["Full-On", "Morning"]),
+                    release_date: Some(chrono::NaiveDate::from_ymd(2012, 1, 24)),
+                    description: Some("Upbeat and melodic full-on psytrance from Australia.".to_string()),
+                    rating: Some(4.24),
+                    votes: Some(135),
+                    downloads: Some(14_857),
+                    cover: Some(img("white-morph-dream-catcher-300x300.jpg")),
+                    tracks: vec![
+                    track(1, None, "Dream Catcher", Some(144)),
+                    track(2, None, "Outer Body Experience", Some(144)),
+                    track(3, None, "Legalize", Some(140)),
+                    track(4, None, "Faulty Software", Some(140)),
+                    ],
+                },
             ),
             (
                 "ektoplazm-va.html",
-                "https://ektoplazm.com/files/VA%20-%20Dividing%202%20Worlds%20-%202018%20-%20MP3.zip",
-                Some("https://creativecommons.org/licenses/by-nc-sa/4.0/"),
-                vec!["Jaira Records"],
-                vec!["Techno", "Techtrance", "Zenonesque"],
-                9,
+                EktoplazmPage {
+                    mp3_link: files("VA%20-%20Dividing%202%20Worlds%20-%202018 - MP3.zip"),
+                    flac_link: Some(files("VA%20-%20Dividing%202%20Worlds%20-%202018 - FLAC.zip")),
+                    wav_link: Some(files("VA%20-%20Dividing%202%20Worlds%20-%202018 - WAV.rar")),
+                    license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+                    labels: strings(&["Jaira Records"]),
+                    tags: strings(&["Techno", "Techtrance", "Zenonesque"]),
+                    release_date: Some(chrono::NaiveDate::from_ymd(2018, 11, 18)),
+                    description: Some("A masterful blend of psychedelic trance and techno.".to_string()),
+                    rating: Some(4.38),
+                    votes: Some(39),
+                    downloads: Some(11_336),
+                    cover: Some(img("va-dividing-2-worlds-300x300.jpg")),
+                    tracks: vec![
+                    track(1, Some("Crennwiick"), "Scape", Some(126)),
+                    track(2, Some("Muggi Dane"), "Gnome Alone (Solarfall Remix)", Some(128)),
+                    track(3, Some("Tonelap"), "The Feeling of Her", Some(125)),
+                    track(4, Some("Isaack"), "Mad Different", Some(128)),
+                    track(5, Some("Bes"), "Don't Move", Some(124)),
+                    track(6, Some("Snok"), "A Spinning Object", Some(126)),
+                    track(7, Some("Beyond Value & David Kras"), "Transverse", Some(125)),
+                    track(8, Some("Qawa Beat"), "Organic Soup", Some(135)),
+                    track(9, Some("Jibba Jabba"), "Motherfucker", Some(132)),
+                    ],
+                },
             ),
             (
                 "ektoplazm-multilabel.html",
-                "https://ektoplazm.com/files/Rose%20Red%20Flechette%20-%20The%20Destruction%20Myth%20-%202018%20-%20MP3.zip",
-                Some("https://creativecommons.org/licenses/by-nc-sa/4.0/"),
-                vec!["Anomalistic Records", "Splatterkore Reck-ords"],
-                vec!["Experimental", "Psycore"],
-                8,
+                EktoplazmPage {
+                    mp3_link: files("Rose%20Red%20Flechette%20-%20The%20Destruction%20Myth%20-%202018 - MP3.zip"),
+                    flac_link: Some(files("Rose%20Red%20Flechette%20-%20The%20Destruction%20Myth%20-%202018 - FLAC.zip")),
+                    wav_link: Some(files("Rose%20Red%20Flechette%20-%20The%20Destruction%20Myth%20-%202018 - WAV.rar")),
+                    license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+                    labels: strings(&["Anomalistic Records", "Splatterkore Reck-ords"]),
+                    tags: strings(&["Experimental", "Psycore"]),
+                    release_date: Some(chrono::NaiveDate::from_ymd(2018, 3, 16)),
+                    description: Some("Extreme poly-rhythmic industrial psychedelia.".to_string()),
+                    rating: Some(4.0),
+                    votes: Some(73),
+                    downloads: Some(10_047),
+                    cover: Some(img("rose-red-flechette-the-destruction-myth-300x300.jpg")),
+                    tracks: vec![
+                    track(1, None, "Let Bleed The Nectars Of Distortion", Some(123)),
+                    track(2, None, "Broadcast Deliriants", Some(175)),
+                    track(3, None, "Penetrative", Some(177)),
+                    track(4, None, "The Singularity Process", Some(203)),
+                    track(5, None, "Demagogue Demi God", Some(109)),
+                    track(6, None, "Infocide", Some(223)),
+                    track(7, None, "Inexorable", Some(234)),
+                    track(8, None, "Ex Abyssus (Machina Ex V2)", Some(129)),
+                    ],
+                },
             ),
             (
                 "ektoplazm-license.html",
-                "https://ektoplazm.com/files/Ekoplex%20-%20Enter%20The%20Dragon%20EP%20-%202008%20-%20MP3.zip",
-                Some("https://creativecommons.org/licenses/by-nc-nd/2.5/ca/"),
-                vec!["Ektoplazm"],
-                vec!["Full-On"],
-                3,
+                EktoplazmPage {
+                    mp3_link: files("Ekoplex%20-%20Enter%20The%20Dragon%20EP%20-%202008 - MP3.zip"),
+                    flac_link: Some(files("Ekoplex%20-%20Enter%20The%20Dragon%20EP%20-%202008 - FLAC.zip")),
+                    wav_link: Some(files("Ekoplex%20-%20Enter%20The%20Dragon%20EP%20-%202008 - WAV.rar")),
+                    license: Some("https://creativecommons.org/licenses/by-nc-nd/2.5/ca/".to_string()),
+                    labels: strings(&["Ektoplazm"]),
+                    tags: strings(&["Full-On"]),
+                    release_date: Some(chrono::NaiveDate::from_ymd(2008, 2, 29)),
+                    description: Some("The debut release from Ekoplex features three distinctly different visions of psychedelic trance.".to_string()),
+                    rating: Some(4.25),
+                    votes: Some(108),
+                    downloads: Some(22_633),
+                    cover: Some(img("ekoplex-enter-the-dragon-ep-300x297.jpg")),
+                    tracks: vec![
+                    track(1, None, "Premonition", Some(145)),
+                    track(2, None, "Astronomical Unit", Some(145)),
+                    track(3, None, "Julia", Some(146)),
+                    ],
+                },
             ),
         ];
 
-        for c in cases {
-            let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-            d.push("tests");
-            d.push("data");
-            d.push(c.0);
-
-            let f = fs::File::open(d).unwrap();
-            let (mp3, license, labels, tags, tracknum) = ektoplazm_parse(f).unwrap();
-            assert_eq!(mp3, c.1.to_string());
-            assert_eq!(license, c.2.map(|x| x.to_string()));
-            assert_eq!(labels, c.3);
-            assert_eq!(tags, c.4);
-            assert_eq!(tracknum, c.5);
+        for (fname, expected) in cases {
+            assert_eq!(
+                ektoplazm_parse(fixture(fname)).unwrap(),
+                expected,
+                "{}",
+                fname
+            );
         }
     }
 
+    #[test]
+    fn listing_ektoplazm() {
+        assert_eq!(
+            ektoplazm_listing("style", "Psy Dub"),
+            "https://ektoplazm.com/style/psy-dub"
+        );
+        assert_eq!(
+            ektoplazm_listing("label", "3L3Mental Records"),
+            "https://ektoplazm.com/label/3l3mental-records"
+        );
+        assert_eq!(
+            ektoplazm_listing("style", "full-on"),
+            "https://ektoplazm.com/style/full-on"
+        );
+        assert_eq!(
+            EktoplazmScraper::new("https://ektoplazm.com/style/psy-dub/", 10).listing,
+            "https://ektoplazm.com/style/psy-dub"
+        );
+
+        let urls = ektoplazm_parse_list(fixture("ektoplazm-style.html")).unwrap();
+        assert_eq!(
+            urls,
+            vec![
+                "https://ektoplazm.com/free-music/globular-entangled-everything",
+                "https://ektoplazm.com/free-music/kalilaskov-as-yet-unknown",
+                "https://ektoplazm.com/free-music/radioactive-sandwich-globular-sorbet-for-the-soul",
+            ]
+        );
+    }
+
+    #[test]
+    fn crosscheck_ektoplazm() {
+        let web = ektoplazm_parse(fixture("ektoplazm-label.html"))
+            .unwrap()
+            .tracks;
+        let track = |title: &str, bpm: Option<u16>| Track {
+            artist: "White Morph".to_string(),
+            title: title.to_string(),
+            bpm,
+            mp3_file: Some(PathBuf::from(format!("{}.mp3", title))),
+            video_file: None,
+            profile: None,
+            youtube_id: None,
+        };
+
+        // missing BPM, half tempo, punctuation is ignored
+        let mut tracks = vec![
+            track("Dream Catcher", Some(144)),
+            track("Outer-Body Experience", None),
+            track("Legalize", Some(70)),
+            track("Faulty Software", Some(140)),
+        ];
+        let notes = crosscheck_tracklist(&web, &mut tracks).unwrap();
+        assert_eq!(notes, vec!["track 02 BPM 144 taken from the web"]);
+        assert_eq!(tracks[1].bpm, Some(144));
+        assert_eq!(tracks[2].bpm, Some(70));
+
+        // bad track numbers, typo, remix only in tags, wrong BPM
+        let mut tracks = vec![
+            track("Legalize (Original Mix)", Some(140)),
+            track("Dream Catcher", Some(144)),
+            track("Outer Body Experiense", Some(144)),
+            track("Faulty Software", Some(150)),
+        ];
+        let notes = crosscheck_tracklist(&web, &mut tracks).unwrap();
+        assert_eq!(notes.len(), 6);
+        assert_eq!(
+            tracks.iter().map(|t| t.title.as_str()).collect::<Vec<_>>(),
+            vec![
+                "Dream Catcher",
+                "Outer Body Experiense",
+                "Legalize (Original Mix)",
+                "Faulty Software"
+            ]
+        );
+        assert_eq!(notes[0], "track 01 \"Dream Catcher\" is tagged as track 02");
+        assert_eq!(notes[5], "track 04 BPM is 140 on the web, 150 in tags");
+
+        // garbage TBPM
+        let mut tracks = vec![
+            track("Dream Catcher", Some(144)),
+            track("Outer-Body Experience", Some(144)),
+            track("Legalize", Some(140)),
+            track("Faulty Software", Some(u16::MAX)),
+        ];
+        let notes = crosscheck_tracklist(&web, &mut tracks).unwrap();
+        assert_eq!(notes, vec!["track 04 BPM is 140 on the web, 65535 in tags"]);
+
+        // different track
+        let mut tracks = vec![
+            track("Dream Catcher", Some(144)),
+            track("Outer Body Experience", Some(144)),
+            track("Legalize", Some(140)),
+            track("Perfect Software", Some(140)),
+        ];
+        let err = crosscheck_tracklist(&web, &mut tracks).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Tracklist on the web does not match the files:\n  \
+             01 Dream Catcher\n  \
+             02 Outer Body Experience\n  \
+             03 Legalize\n\
+             ! 04 Faulty Software / Perfect Software\n"
+        );
+
+        // missing track
+        tracks.pop();
+        let err = crosscheck_tracklist(&web, &mut tracks).unwrap_err();
+        assert!(err.to_string().ends_with("\n+ 04 Faulty Software\n"));
+
+        assert!(crosscheck_tracklist(&[], &mut tracks).unwrap().len() == 1);
+    }
+
     #[test]
     fn unpack_id3_ektoplazm() {
         let f = fixture("Risingson - Predestination - 2016 - MP3.zip");
@@ -471,8 +1089,8 @@ mod tests {
             .collect::<HashSet<(_, _)>>();
         assert_eq!(contents, expected);
 
-        let (album_title, album_artist, album_year, tracks) = read_id3(dir.path()).unwrap();
-        assert_eq!(album_title, "Predestination".to_string());
+        let (album_title, album_artist, album_year, tracks) = read_tags(dir.path()).unwrap();
+        assert_eq!(album_title, Some("Predestination".to_string()));
         assert_eq!(album_artist, Some("Risingson".to_string()));
         assert_eq!(album_year, Some(2016));
         assert_eq!(
@@ -484,6 +1102,7 @@ mod tests {
                     bpm: Some(88),
                     mp3_file: Some(PathBuf::from("01 - Risingson - Digital Being.mp3")),
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
                 Track {
@@ -492,6 +1111,7 @@ mod tests {
                     bpm: Some(97),
                     mp3_file: Some(PathBuf::from("02 - Risingson - Robosapiens.mp3")),
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
                 Track {
@@ -500,40 +1120,143 @@ mod tests {
                     bpm: Some(88),
                     mp3_file: Some(PathBuf::from("03 - Risingson - Predestination.mp3")),
                     video_file: None,
+                    profile: None,
                     youtube_id: None,
                 },
             ]
         );
     }
 
+    // Minimal FLAC file: STREAMINFO followed by Vorbis comments, no audio frames.
+    fn write_flac(path: &Path, comments: &[&str]) {
+        let mut data = b"fLaC".to_vec();
+        data.extend(&[0x00, 0x00, 0x00, 34]);
+        data.extend(&[0x10, 0x00, 0x10, 0x00]); // block sizes
+        data.extend(&[0; 6]); // frame sizes
+        data.extend(&[0x0a, 0xc4, 0x42, 0xf0]); // 44100 Hz, 2 channels, 16 bits
+        data.extend(&[0; 4]); // total samples
+        data.extend(&[0; 16]); // MD5
+
+        let mut block: Vec<u8> = Vec::new();
+        let vendor = b"ektoboat";
+        block.extend(&(vendor.len() as u32).to_le_bytes());
+        block.extend(vendor);
+        block.extend(&(comments.len() as u32).to_le_bytes());
+        for c in comments {
+            block.extend(&(c.len() as u32).to_le_bytes());
+            block.extend(c.as_bytes());
+        }
+        let len = (block.len() as u32).to_be_bytes();
+        data.extend(&[0x84, len[1], len[2], len[3]]);
+        data.extend(block);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn read_tags_flac() {
+        let dir = tempfile::tempdir().unwrap();
+        write_flac(
+            &dir.path().join("02.flac"),
+            &[
+                "TRACKNUMBER=2/2",
+                "ARTIST=Risingson",
+                "TITLE=Robosapiens",
+                "ALBUM=Predestination",
+                "ALBUMARTIST=Risingson",
+                "DATE=2016-05-01",
+            ],
+        );
+        write_flac(
+            &dir.path().join("01.flac"),
+            &[
+                "TRACKNUMBER=1",
+                "ARTIST=Risingson",
+                "TITLE=Digital Being",
+                "BPM=88",
+            ],
+        );
+
+        let (album_title, album_artist, album_year, tracks) = read_tags(dir.path()).unwrap();
+        assert_eq!(album_title, Some("Predestination".to_string()));
+        assert_eq!(album_artist, Some("Risingson".to_string()));
+        assert_eq!(album_year, Some(2016));
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|t| (t.title.as_str(), t.bpm, t.mp3_file.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Digital Being", Some(88), Some(PathBuf::from("01.flac"))),
+                ("Robosapiens", None, Some(PathBuf::from("02.flac"))),
+            ]
+        );
+    }
+
     #[test]
     fn description_ektoplazm() {
-        let testcases = [(
-            Album {
-                url: "https://ektoplazm.com/free-music/asdfasdf".to_string(),
-
-                artist: Some("Risingson".to_string()),
-                title: "Forgot".to_string(),
-                license: None,
-                year: None,
-                labels: vec![],
-                tags: vec![],
-                tracks: vec![],
-                youtube_id: None,
-            },
-            Track {
+        let album = Album {
+            url: "https://ektoplazm.com/free-music/asdfasdf".to_string(),
+
+            artist: Some("Risingson".to_string()),
+            title: "Forgot".to_string(),
+            license: None,
+            year: None,
+            labels: vec![],
+            tags: vec![],
+            tracks: vec![Track {
                 artist: "Risingson".to_string(),
                 title: "Digital Being".to_string(),
                 bpm: Some(88),
                 mp3_file: Some(PathBuf::from("01 - Risingson - Digital Being.mp3")),
                 video_file: None,
+                profile: None,
                 youtube_id: None,
-            },
-            "exp",
-        )];
+            }],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        let no_templates = tempfile::tempdir().unwrap();
 
-        for tc in &testcases {
-            println!("{}", description(&tc.0, &tc.1).unwrap());
-        }
+        assert_eq!(
+            render_template(
+                no_templates.path(),
+                TemplateKind::VideoDescription,
+                &album,
+                Some(0)
+            )
+            .unwrap(),
+            "Download the full album from Ektoplazm: https://ektoplazm.com/free-music/asdfasdf
+
+Artist: Risingson
+Track: Digital Being
+Album: Forgot
+Track number: 01
+BPM: 88"
+        );
+
+        let album = Album {
+            download_url: Some("https://ektoplazm.com/files/asdf-MP3.zip".to_string()),
+            ..album
+        };
+        assert_eq!(
+            render_template(
+                no_templates.path(),
+                TemplateKind::PlaylistDescription,
+                &album,
+                None
+            )
+            .unwrap(),
+            "Album page on Ektoplazm: https://ektoplazm.com/free-music/asdfasdf
+Free download: https://ektoplazm.com/files/asdf-MP3.zip
+
+Forgot by Risingson
+
+Tracklist:
+01. Risingson - Digital Being (88 BPM)"
+        );
     }
 }
@@ -0,0 +1,457 @@
+use super::*;
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub struct ArchiveOrg {}
+
+impl Source for ArchiveOrg {
+    fn name(&self) -> &'static str {
+        "archiveorg"
+    }
+
+    fn belongs(&self, url: &str) -> bool {
+        archiveorg_identifier(url).is_some()
+    }
+
+    fn fetch(&self, url: &str, mp3_dir: &Path, format: AudioFormat) -> Result<Album, util::Error> {
+        log::info!("Fetching {}", url);
+        let identifier = archiveorg_identifier(url).ok_or("Not an archive.org item URL")?;
+        let res = download(&format!("https://archive.org/metadata/{}", identifier))?;
+        let item = archiveorg_parse(res, format)?;
+        if item.tracks.is_empty() {
+            return Err(util::Error::new("Item has no audio files"));
+        }
+
+        let tmpdir = tempfile::Builder::new()
+            .prefix("0-ektoboat-tmp-")
+            .tempdir_in(mp3_dir)?;
+        let mut tracks = Vec::new();
+        for f in item
+            .images
+            .iter()
+            .chain(item.tracks.iter().map(|t| &t.file))
+        {
+            let mut res = download(&format!(
+                "https://archive.org/download/{}/{}",
+                identifier,
+                quote_path(f)
+            ))?;
+            let dest = tmpdir.path().join(basename(f));
+            log::debug!("Saving {:?} -> {:?}", f, dest);
+            copy(&mut res, &mut std::fs::File::create(dest)?)?;
+        }
+        for t in &item.tracks {
+            tracks.push(Track {
+                artist: t.artist.clone(),
+                title: t.title.clone(),
+                bpm: None,
+                mp3_file: Some(PathBuf::from(basename(&t.file))),
+                video_file: None,
+                profile: None,
+                youtube_id: None,
+            });
+        }
+
+        let album = Album {
+            url: url.to_string(),
+
+            artist: item.artist,
+            title: item.title,
+            license: item.license,
+            year: item.year,
+            labels: item.labels,
+            tags: item.tags,
+            tracks,
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+
+        move_album(tmpdir, &album, mp3_dir)?;
+        Ok(album)
+    }
+
+    fn default_template(&self, kind: TemplateKind) -> String {
+        download_link_template(kind, "the Internet Archive")
+    }
+}
+
+fn archiveorg_identifier(url: &str) -> Option<&str> {
+    lazy_static! {
+        static ref URL: Regex =
+            Regex::new(r"^https?://(www\.)?archive\.org/details/([^/?#]+)/?$").unwrap();
+    }
+    URL.captures(url).and_then(|c| c.get(2)).map(|m| m.as_str())
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// Percent-encodes file path for use in the download URL.
+fn quote_path(path: &str) -> String {
+    let mut res = String::new();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                res.push(b as char)
+            }
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+#[derive(Debug, PartialEq)]
+struct ArchiveOrgItem {
+    artist: Option<String>,
+    title: String,
+    year: Option<u16>,
+    license: Option<String>,
+    labels: Vec<String>,
+    tags: Vec<String>,
+    tracks: Vec<ArchiveOrgTrack>,
+    images: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct ArchiveOrgTrack {
+    file: String,
+    artist: String,
+    title: String,
+}
+
+// Subset of the metadata API response.
+#[derive(Deserialize)]
+struct IAItem {
+    files: Vec<IAFile>,
+    metadata: IAMetadata,
+}
+
+#[derive(Deserialize)]
+struct IAFile {
+    name: String,
+    source: String,
+    format: Option<String>,
+    original: Option<String>,
+    title: Option<String>,
+    creator: Option<String>,
+    track: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IAMetadata {
+    title: String,
+    creator: Option<Value>,
+    date: Option<String>,
+    licenseurl: Option<String>,
+    subject: Option<Value>,
+    publisher: Option<Value>,
+}
+
+// Metadata fields can be either a string or a list of strings, subjects are also often
+// semicolon-separated.
+fn string_list(v: &Option<Value>) -> Vec<String> {
+    let items = match v {
+        Some(Value::String(s)) => vec![s.as_str()],
+        Some(Value::Array(a)) => a.iter().filter_map(|x| x.as_str()).collect(),
+        _ => vec![],
+    };
+    items
+        .into_iter()
+        .flat_map(|s| s.split(';'))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn track_number(f: &IAFile) -> Option<u32> {
+    f.track.as_ref()?.split('/').next()?.trim().parse().ok()
+}
+
+fn archiveorg_parse<T: Read>(res: T, audio_format: AudioFormat) -> util::Result<ArchiveOrgItem> {
+    let item: IAItem = serde_json::from_reader(res)?;
+    let format = |f: &IAFile| f.format.clone().unwrap_or_default();
+
+    let mut mp3s: Vec<&IAFile> = vec![];
+    if audio_format == AudioFormat::Flac {
+        mp3s = item
+            .files
+            .iter()
+            .filter(|f| f.source == "original" && format(f) == "Flac")
+            .collect();
+    }
+    // Prefer the VBR MP3s generated from lossless originals, fall back to original MP3s.
+    if mp3s.is_empty() {
+        mp3s = item
+            .files
+            .iter()
+            .filter(|f| f.source == "derivative" && format(f) == "VBR MP3")
+            .collect();
+    }
+    if mp3s.is_empty() {
+        mp3s = item
+            .files
+            .iter()
+            .filter(|f| f.source == "original" && format(f).ends_with("MP3"))
+            .collect();
+    }
+
+    let original = |f: &'_ IAFile| -> Option<&IAFile> {
+        let name = f.original.as_ref()?;
+        item.files.iter().find(|o| &o.name == name)
+    };
+
+    let artists = string_list(&item.metadata.creator);
+    let album_artist = artists.first().cloned();
+
+    let mut tracks: Vec<(Option<u32>, usize, ArchiveOrgTrack)> = mp3s
+        .into_iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let orig = original(f);
+            let num = orig.and_then(track_number).or_else(|| track_number(f));
+            let title = f
+                .title
+                .clone()
+                .or_else(|| orig.and_then(|o| o.title.clone()))
+                .unwrap_or_else(|| {
+                    let name = basename(&f.name);
+                    name.rsplitn(2, '.').last().unwrap_or(name).to_string()
+                });
+            let artist = orig
+                .and_then(|o| o.creator.clone())
+                .or_else(|| f.creator.clone())
+                .or_else(|| album_artist.clone())
+                .unwrap_or_else(|| "Unknown Artist".to_string());
+            (
+                num,
+                i,
+                ArchiveOrgTrack {
+                    file: f.name.clone(),
+                    artist,
+                    title,
+                },
+            )
+        })
+        .collect();
+    // tracks without number go last, in the order of the file listing
+    tracks.sort_by_key(|t| (t.0.is_none(), t.0, t.1));
+
+    let images = item
+        .files
+        .iter()
+        .filter(|f| f.source == "original" && (format(f) == "JPEG" || format(f) == "PNG"))
+        .map(|f| f.name.clone())
+        .collect();
+
+    let year = item
+        .metadata
+        .date
+        .as_ref()
+        .and_then(|d| d.get(0..4))
+        .and_then(|y| y.parse().ok());
+
+    Ok(ArchiveOrgItem {
+        artist: album_artist.filter(|a| a != "VA" && a != "Various Artists"),
+        title: item.metadata.title,
+        year,
+        license: item.metadata.licenseurl,
+        labels: string_list(&item.metadata.publisher),
+        tags: string_list(&item.metadata.subject),
+        tracks: tracks.into_iter().map(|t| t.2).collect(),
+        images,
+    })
+}
+
+pub struct ArchiveOrgScraper {
+    collection: String,
+    next_page: u32,
+    urls: VecDeque<String>,
+}
+
+impl ArchiveOrgScraper {
+    pub fn new(collection: &str) -> ArchiveOrgScraper {
+        ArchiveOrgScraper {
+            collection: collection.to_string(),
+            next_page: 1,
+            urls: VecDeque::new(),
+        }
+    }
+}
+
+impl std::iter::Iterator for ArchiveOrgScraper {
+    type Item = Result<String, util::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.urls.is_empty() {
+            self.urls = match archiveorg_scrape_list(&self.collection, self.next_page) {
+                Err(e) => return Some(Err(e)),
+                Ok(urls) => urls,
+            };
+            self.next_page += 1;
+        }
+
+        self.urls.pop_front().map(Ok)
+    }
+}
+
+#[derive(Deserialize)]
+struct IASearch {
+    response: IASearchResponse,
+}
+
+#[derive(Deserialize)]
+struct IASearchResponse {
+    docs: Vec<IASearchDoc>,
+}
+
+#[derive(Deserialize)]
+struct IASearchDoc {
+    identifier: String,
+}
+
+fn archiveorg_scrape_list(collection: &str, page: u32) -> Result<VecDeque<String>, util::Error> {
+    let url = format!(
+        "https://archive.org/advancedsearch.php?q=collection%3A{}&fl%5B%5D=identifier&sort%5B%5D=publicdate+asc&rows=100&page={}&output=json",
+        quote_path(collection),
+        page
+    );
+    archiveorg_parse_list(download(&url)?)
+}
+
+fn archiveorg_parse_list<T: Read>(res: T) -> Result<VecDeque<String>, util::Error> {
+    let search: IASearch = serde_json::from_reader(res)?;
+    Ok(search
+        .response
+        .docs
+        .into_iter()
+        .map(|d| format!("https://archive.org/details/{}", d.identifier))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    #[test]
+    fn belongs_archiveorg() {
+        let ia = ArchiveOrg {};
+        assert!(ia.belongs("https://archive.org/details/enrmp120"));
+        assert!(ia.belongs("https://archive.org/details/enrmp120/"));
+        assert!(!ia.belongs("https://archive.org/details/enrmp120/01.mp3"));
+        assert!(!ia.belongs("https://archive.org/search.php?query=enrmp120"));
+        assert_eq!(
+            archiveorg_identifier("http://www.archive.org/details/mtk073"),
+            Some("mtk073")
+        );
+    }
+
+    #[test]
+    fn parse_archiveorg() {
+        let track = |file: &str, artist: &str, title: &str| ArchiveOrgTrack {
+            file: file.to_string(),
+            artist: artist.to_string(),
+            title: title.to_string(),
+        };
+
+        let item = archiveorg_parse(fixture("archiveorg-flac.json"), AudioFormat::Mp3).unwrap();
+        assert_eq!(
+            item,
+            ArchiveOrgItem {
+                artist: Some("Sunny Rain".to_string()),
+                title: "Kaleidoscope Memories".to_string(),
+                year: Some(2006),
+                license: Some("http://creativecommons.org/licenses/by-nc-nd/2.5/pt/".to_string()),
+                labels: vec!["Enough Records".to_string()],
+                tags: vec!["ambient", "downtempo", "experimental"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                tracks: vec![
+                    track(
+                        "01-sunny_rain-intro_to_silence.mp3",
+                        "Sunny Rain",
+                        "Intro To Silence"
+                    ),
+                    track(
+                        "02-sunny_rain-mirrored_stones.mp3",
+                        "Sunny Rain",
+                        "Mirrored Stones"
+                    ),
+                    track(
+                        "03-sunny_rain-feat_ophelia-last_light.mp3",
+                        "Sunny Rain feat. Ophelia",
+                        "Last Light"
+                    ),
+                ],
+                images: vec!["enrmp120_cover.jpg".to_string()],
+            }
+        );
+
+        let item = archiveorg_parse(fixture("archiveorg-mp3.json"), AudioFormat::Flac).unwrap();
+        assert_eq!(
+            item,
+            ArchiveOrgItem {
+                artist: Some("Omnimotion".to_string()),
+                title: "Ocean Drive EP".to_string(),
+                year: Some(2005),
+                license: Some("http://creativecommons.org/licenses/by-nc-sa/2.0/de/".to_string()),
+                labels: vec![],
+                tags: vec!["techno".to_string(), "minimal".to_string()],
+                tracks: vec![
+                    track(
+                        "mtk073_01_omnimotion_-_ocean_drive.mp3",
+                        "Omnimotion",
+                        "Ocean Drive"
+                    ),
+                    track(
+                        "mtk073_02_omnimotion_-_wolkenkratzer.mp3",
+                        "Omnimotion",
+                        "Wolkenkratzer"
+                    ),
+                ],
+                images: vec!["mtk073_-_folder.jpg".to_string()],
+            }
+        );
+
+        let item = archiveorg_parse(fixture("archiveorg-flac.json"), AudioFormat::Flac).unwrap();
+        assert_eq!(
+            item.tracks
+                .iter()
+                .map(|t| t.file.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "01-sunny_rain-intro_to_silence.flac",
+                "02-sunny_rain-mirrored_stones.flac",
+                "03-sunny_rain-feat_ophelia-last_light.flac",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_archiveorg_list() {
+        assert_eq!(
+            archiveorg_parse_list(fixture("archiveorg-search.json")).unwrap(),
+            vec![
+                "https://archive.org/details/enrmp001",
+                "https://archive.org/details/enrmp002",
+                "https://archive.org/details/enrmp120",
+            ]
+        );
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(
+            quote_path("Sunny Rain/01 - Intro (édit).mp3"),
+            "Sunny%20Rain/01%20-%20Intro%20%28%C3%A9dit%29.mp3"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use super::*;
+
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::Deserialize;
+
+pub struct Bandcamp {}
+
+impl Source for Bandcamp {
+    fn name(&self) -> &'static str {
+        "bandcamp"
+    }
+
+    fn belongs(&self, url: &str) -> bool {
+        lazy_static! {
+            static ref URL: Regex =
+                Regex::new(r"^https?://[a-z0-9-]+\.bandcamp\.com/album/[^/]+").unwrap();
+        }
+        URL.is_match(url)
+    }
+
+    fn fetch(&self, url: &str, mp3_dir: &Path, format: AudioFormat) -> Result<Album, util::Error> {
+        log::info!("Fetching {}", url);
+        let res = download(url)?;
+        let page = bandcamp_parse(res)?;
+        let download_page = page
+            .free_download
+            .as_ref()
+            .ok_or("Album does not offer free download")?;
+        let res = download(download_page)?;
+        let zip_link = bandcamp_parse_download(res, format)?;
+        let zip_link = bandcamp_resolve_download(&zip_link)?;
+
+        let tmpdir = download_unpack(&zip_link, mp3_dir)?;
+        let (album_title, album_artist, album_year, mut tracks) = read_tags(tmpdir.path())?;
+        let web_tracks: Vec<WebTrack> = page
+            .tracks
+            .into_iter()
+            .map(|t| WebTrack {
+                num: t.num,
+                artist: Some(t.artist),
+                title: t.title,
+                bpm: None,
+            })
+            .collect();
+        let notes = crosscheck_tracklist(&web_tracks, &mut tracks)?;
+        for note in &notes {
+            log::warn!("{}", note);
+        }
+        let year = page.release_date.map(|d| chrono::Datelike::year(&d) as u16);
+        let album = Album {
+            url: url.to_string(),
+
+            artist: album_artist.or(Some(page.artist).filter(|a| a != "Various Artists")),
+            title: album_title.unwrap_or(page.title),
+            license: page.license,
+            year: album_year.or(year),
+            labels: page.labels,
+            tags: page.tags,
+            tracks,
+            youtube_id: None,
+            release_date: page.release_date,
+            description: None,
+            download_url: Some(zip_link),
+            album_video_id: None,
+            notes,
+        };
+
+        move_album(tmpdir, &album, mp3_dir)?;
+        Ok(album)
+    }
+
+    fn default_template(&self, kind: TemplateKind) -> String {
+        download_link_template(kind, "Bandcamp")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct BandcampPage {
+    artist: String,
+    title: String,
+    release_date: Option<chrono::NaiveDate>,
+    license: Option<String>,
+    labels: Vec<String>,
+    tags: Vec<String>,
+    tracks: Vec<BandcampTrack>,
+    free_download: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct BandcampTrack {
+    num: u32,
+    artist: String,
+    title: String,
+}
+
+// Subset of the data-tralbum attribute.
+#[derive(Deserialize)]
+struct Tralbum {
+    artist: String,
+    current: TralbumCurrent,
+    album_release_date: Option<String>,
+    #[serde(rename = "freeDownloadPage")]
+    free_download_page: Option<String>,
+    trackinfo: Vec<TralbumTrack>,
+}
+
+#[derive(Deserialize)]
+struct TralbumCurrent {
+    title: String,
+    release_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TralbumTrack {
+    track_num: Option<u32>,
+    artist: Option<String>,
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct TralbumBand {
+    name: String,
+}
+
+// Subset of the data-blob attribute on the free download page.
+#[derive(Deserialize)]
+struct DownloadBlob {
+    download_items: Vec<DownloadItem>,
+}
+
+#[derive(Deserialize)]
+struct DownloadItem {
+    downloads: HashMap<String, DownloadLink>,
+}
+
+#[derive(Deserialize)]
+struct DownloadLink {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct StatDownload {
+    result: String,
+    download_url: Option<String>,
+}
+
+fn script_json<'a, T: Deserialize<'a>>(doc: &'a Document, attr: &str) -> util::Result<T> {
+    let data = doc
+        .find(Name("script"))
+        .filter_map(|tag| tag.attr(attr))
+        .next()
+        .ok_or_else(|| util::Error::new(&format!("Failed to find {}", attr)))?;
+    Ok(serde_json::from_str(data)?)
+}
+
+fn bandcamp_parse<T: Read>(res: T) -> util::Result<BandcampPage> {
+    let doc = Document::from_read(res)?;
+    let tralbum: Tralbum = script_json(&doc, "data-tralbum")?;
+    let band: TralbumBand = script_json(&doc, "data-band")?;
+
+    let release_date = tralbum
+        .album_release_date
+        .as_ref()
+        .or(tralbum.current.release_date.as_ref())
+        .and_then(|d| chrono::NaiveDateTime::parse_from_str(d, "%d %b %Y %H:%M:%S GMT").ok())
+        .map(|d| d.date());
+
+    let license = doc
+        .find(Attr("id", "license").descendant(Name("a")))
+        .filter_map(|tag| tag.attr("href"))
+        .find(|href| href.contains("creativecommons"))
+        .map(|x| x.to_string());
+
+    let tags = doc
+        .find(Class("tralbum-tags").descendant(Class("tag")))
+        .map(|tag| tag.text().trim().to_string())
+        .collect();
+
+    // label accounts publish albums of other artists
+    let labels = if band.name != tralbum.artist {
+        vec![band.name]
+    } else {
+        vec![]
+    };
+
+    let artist = tralbum.artist;
+    let tracks = tralbum
+        .trackinfo
+        .into_iter()
+        .enumerate()
+        .map(|(i, t)| BandcampTrack {
+            num: t.track_num.unwrap_or(i as u32 + 1),
+            artist: t.artist.unwrap_or_else(|| artist.clone()),
+            title: t.title,
+        })
+        .collect();
+
+    Ok(BandcampPage {
+        artist,
+        title: tralbum.current.title,
+        release_date,
+        license,
+        labels,
+        tags,
+        tracks,
+        free_download: tralbum.free_download_page,
+    })
+}
+
+fn bandcamp_parse_download<T: Read>(res: T, format: AudioFormat) -> util::Result<String> {
+    let doc = Document::from_read(res)?;
+    let blob = doc
+        .find(Attr("id", "pagedata"))
+        .filter_map(|tag| tag.attr("data-blob"))
+        .next()
+        .ok_or("Failed to find download data")?;
+    let blob: DownloadBlob = serde_json::from_str(blob)?;
+
+    let encoding = match format {
+        AudioFormat::Mp3 => "mp3-320",
+        AudioFormat::Flac => "flac",
+    };
+    blob.download_items
+        .into_iter()
+        .filter_map(|mut item| item.downloads.remove(encoding))
+        .map(|link| link.url)
+        .next()
+        .ok_or_else(|| util::Error::new("Failed to find download link"))
+}
+
+// The link from download page only prepares the archive, actual location is obtained from
+// the statdownload endpoint.
+fn bandcamp_resolve_download(url: &str) -> util::Result<String> {
+    let stat_url = format!("{}&.vrs=1", url.replacen("/download/", "/statdownload/", 1));
+    let res = download(&stat_url)?;
+    let stat: StatDownload = serde_json::from_reader(res)?;
+    match stat.download_url {
+        Some(u) if stat.result == "ok" => Ok(u),
+        _ => Err(util::Error::new(&format!(
+            "Bandcamp download not ready: {}",
+            stat.result
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    #[test]
+    fn belongs_bandcamp() {
+        let bc = Bandcamp {};
+        assert!(bc.belongs("https://globular.bandcamp.com/album/entangled-everything"));
+        assert!(!bc.belongs("https://globular.bandcamp.com/track/popping-out"));
+        assert!(!bc.belongs("https://ektoplazm.com/free-music/globular-entangled-everything"));
+    }
+
+    #[test]
+    fn parse_bandcamp() {
+        let track = |num: u32, artist: &str, title: &str| BandcampTrack {
+            num,
+            artist: artist.to_string(),
+            title: title.to_string(),
+        };
+
+        let page = bandcamp_parse(fixture("bandcamp-album.html")).unwrap();
+        assert_eq!(
+            page,
+            BandcampPage {
+                artist: "Globular".to_string(),
+                title: "Entangled Everything".to_string(),
+                release_date: Some(chrono::NaiveDate::from_ymd(2018, 12, 18)),
+                license: Some("http://creativecommons.org/licenses/by-nc-sa/3.0/".to_string()),
+                labels: vec![],
+                tags: vec!["electronic", "Downtempo", "Psy Dub", "Bristol"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                tracks: vec![
+                    track(1, "Globular", "Popping Out"),
+                    track(2, "Globular", "For The Time Being"),
+                    track(3, "Globular", "The Chalice"),
+                    track(4, "Globular", "Total Perspective Vortex"),
+                ],
+                free_download: Some("https://globular.bandcamp.com/download?id=1851327218&ts=1587654321.1234567890&sig=0a1b2c3d4e5f60718293a4b5c6d7e8f9&type=album".to_string()),
+            }
+        );
+
+        let page = bandcamp_parse(fixture("bandcamp-va.html")).unwrap();
+        assert_eq!(
+            page,
+            BandcampPage {
+                artist: "Various Artists".to_string(),
+                title: "Dividing 2 Worlds".to_string(),
+                release_date: Some(chrono::NaiveDate::from_ymd(2018, 3, 5)),
+                license: None,
+                labels: vec!["Jaira Records".to_string()],
+                tags: vec!["electronic", "techno", "techtrance"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                tracks: vec![
+                    track(1, "Dekel", "Entropy"),
+                    track(2, "Roof Raiser", "Sound Of The Cosmos"),
+                    track(3, "Ajja", "Rotation"),
+                ],
+                free_download: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_bandcamp_download() {
+        assert_eq!(
+            bandcamp_parse_download(fixture("bandcamp-download.html"), AudioFormat::Mp3).unwrap(),
+            "https://p4.bcbits.com/download/album/abcdef0123456789/mp3-320/1851327218?id=1851327218&sig=22&sitem_id=77&token=1587654321_bb"
+        );
+        assert_eq!(
+            bandcamp_parse_download(fixture("bandcamp-download.html"), AudioFormat::Flac).unwrap(),
+            "https://p4.bcbits.com/download/album/abcdef0123456789/flac/1851327218?id=1851327218&sig=33&sitem_id=77&token=1587654321_cc"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use super::*;
+
+use serde::Deserialize;
+
+pub struct Local {}
+
+// Metadata that is not part of the ID3 tags, read from ektoboat.json inside album directory or
+// from JSON file next to the ZIP archive (album.zip -> album.json).
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct Sidecar {
+    license: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Sidecar {
+    fn read(path: &Path) -> Result<Sidecar, util::Error> {
+        if !path.is_file() {
+            log::debug!("No sidecar file {:?}", path);
+            return Ok(Sidecar::default());
+        }
+        log::debug!("Reading sidecar file {:?}", path);
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+}
+
+const SIDECAR_NAME: &str = "ektoboat.json";
+
+fn local_path(url: &str) -> Option<&Path> {
+    url.strip_prefix("file://").map(Path::new)
+}
+
+// Turns path to an existing file or directory into file:// URL, other strings are returned as is.
+pub fn path_to_url(s: &str) -> String {
+    if s.contains("://") {
+        return s.to_string();
+    }
+    match Path::new(s).canonicalize() {
+        Ok(p) => format!("file://{}", p.display()),
+        Err(_) => s.to_string(),
+    }
+}
+
+impl Source for Local {
+    fn name(&self) -> &'static str {
+        "local" // no public page to link to, the plain default templates are used
+    }
+
+    fn belongs(&self, url: &str) -> bool {
+        local_path(url).is_some()
+    }
+
+    // Takes whatever audio format is there.
+    fn fetch(&self, url: &str, mp3_dir: &Path, _: AudioFormat) -> Result<Album, util::Error> {
+        let path = local_path(url).ok_or("Not a file:// URL")?;
+        log::info!("Reading {:?}", path);
+
+        let (tmpdir, sidecar) = if path.is_dir() {
+            let tmpdir = tempfile::Builder::new()
+                .prefix("0-ektoboat-tmp-")
+                .tempdir_in(mp3_dir)?;
+            copy_flat(path, tmpdir.path())?;
+            (tmpdir, Sidecar::read(&path.join(SIDECAR_NAME))?)
+        } else {
+            let f = std::fs::File::open(path)?;
+            (
+                unpack(f, mp3_dir)?,
+                Sidecar::read(&path.with_extension("json"))?,
+            )
+        };
+
+        let (album_title, album_artist, album_year, tracks) = read_tags(tmpdir.path())?;
+        let album = Album {
+            url: url.to_string(),
+
+            artist: album_artist,
+            title: album_title.ok_or(NO_ALBUM_TITLE)?,
+            license: sidecar.license,
+            year: album_year,
+            labels: sidecar.labels,
+            tags: sidecar.tags,
+            tracks,
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+
+        move_album(tmpdir, &album, mp3_dir)?;
+        Ok(album)
+    }
+}
+
+// Copies all files from the directory tree into single directory, same as unpack does.
+fn copy_flat(src: &Path, dest: &Path) -> Result<(), util::Error> {
+    for e in std::fs::read_dir(src)? {
+        let e = e?;
+        let ft = e.file_type()?;
+        if ft.is_dir() {
+            copy_flat(&e.path(), dest)?;
+        } else if ft.is_file() {
+            let target = dest.join(e.file_name());
+            log::debug!("Copy {:?} -> {:?}", e.path(), target);
+            std::fs::copy(e.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_mp3(path: &Path, num: u32, title: &str) {
+        std::fs::write(path, b"").unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Risingson");
+        tag.set_album_artist("Risingson");
+        tag.set_album("Predestination");
+        tag.set_title(title);
+        tag.set_track(num);
+        tag.set_year(2016);
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+    }
+
+    fn check_album(album: &Album, mp3_dir: &Path, license: Option<&str>) {
+        assert_eq!(album.artist, Some("Risingson".to_string()));
+        assert_eq!(album.title, "Predestination");
+        assert_eq!(album.year, Some(2016));
+        assert_eq!(album.license, license.map(String::from));
+        assert_eq!(
+            album
+                .tracks
+                .iter()
+                .map(|t| t.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Digital Being", "Robosapiens"]
+        );
+        assert!(album.has_mp3(mp3_dir));
+        assert!(album.dirname(mp3_dir).join("cover.jpg").is_file());
+    }
+
+    #[test]
+    fn local_dir() {
+        let src = tempfile::tempdir().unwrap();
+        let mp3_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(src.path().join("cd1")).unwrap();
+        write_mp3(&src.path().join("cd1/02.mp3"), 2, "Robosapiens");
+        write_mp3(&src.path().join("01.mp3"), 1, "Digital Being");
+        std::fs::write(src.path().join("cover.jpg"), b"").unwrap();
+        std::fs::write(
+            src.path().join(SIDECAR_NAME),
+            r#"{"license": "https://creativecommons.org/licenses/by-nc-sa/4.0/", "labels": ["Ektoplazm"]}"#,
+        )
+        .unwrap();
+
+        let url = path_to_url(src.path().to_str().unwrap());
+        assert!(url.starts_with("file:///"));
+        let local = Local {};
+        assert!(local.belongs(&url));
+        let album = local.fetch(&url, mp3_dir.path(), AudioFormat::Mp3).unwrap();
+        check_album(
+            &album,
+            mp3_dir.path(),
+            Some("https://creativecommons.org/licenses/by-nc-sa/4.0/"),
+        );
+        assert_eq!(album.labels, vec!["Ektoplazm".to_string()]);
+        assert!(render_template(
+            mp3_dir.path(),
+            TemplateKind::VideoDescription,
+            &album,
+            Some(0)
+        )
+        .unwrap()
+        .starts_with("Artist: Risingson"));
+    }
+
+    #[test]
+    fn local_zip() {
+        let src = tempfile::tempdir().unwrap();
+        let mp3_dir = tempfile::tempdir().unwrap();
+        let zip_path = src.path().join("Risingson - Predestination.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for (num, title) in &[(1, "Digital Being"), (2, "Robosapiens")] {
+            let mp3 = src.path().join("track.mp3");
+            write_mp3(&mp3, *num, title);
+            zip.start_file(
+                format!("Risingson/{:02}.mp3", num),
+                zip::write::FileOptions::default(),
+            )
+            .unwrap();
+            zip.write_all(&std::fs::read(&mp3).unwrap()).unwrap();
+        }
+        zip.start_file("cover.jpg", zip::write::FileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let url = format!("file://{}", zip_path.display());
+        let album = Local {}
+            .fetch(&url, mp3_dir.path(), AudioFormat::Flac)
+            .unwrap();
+        check_album(&album, mp3_dir.path(), None);
+        assert!(album.labels.is_empty());
+    }
+}
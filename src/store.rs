@@ -1,4 +1,4 @@
-use crate::model::{Album, Blacklist, Track};
+use crate::model::{Album, Blacklist, BlacklistField, BlacklistRule, Track};
 use crate::util;
 use crate::youtube;
 
@@ -12,6 +12,157 @@ pub struct Store {
     conn: rusqlite::Connection,
 }
 
+// One attempt to upload video or create playlist, including the retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadEvent {
+    pub kind: UploadKind,
+    pub album_url: String,
+    pub track: Option<u32>, // number within the album, None for playlists and album videos
+    pub started: chrono::DateTime<chrono::Utc>,
+    pub finished: chrono::DateTime<chrono::Utc>,
+    pub bytes: Option<u64>,
+    pub attempts: u32,
+    pub youtube_id: Option<String>,
+    pub error: Option<String>, // None on success
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadKind {
+    Video,
+    Playlist,
+    AlbumVideo,
+}
+
+impl UploadKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            UploadKind::Video => "video",
+            UploadKind::Playlist => "playlist",
+            UploadKind::AlbumVideo => "album-video",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<UploadKind> {
+        [
+            UploadKind::Video,
+            UploadKind::Playlist,
+            UploadKind::AlbumVideo,
+        ]
+        .iter()
+        .cloned()
+        .find(|k| k.as_str() == name)
+    }
+}
+
+impl std::fmt::Display for UploadKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl rusqlite::types::FromSql for UploadKind {
+    fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Self> {
+        String::column_result(value).and_then(|s| {
+            UploadKind::from_name(&s).ok_or(rusqlite::types::FromSqlError::InvalidType)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueState {
+    Pending,
+    InProgress,
+    Done,
+    FailedPermanent,
+    FailedRetryable,
+    SkippedBlacklist,
+    SkippedNoLicense,
+    SkippedLicense, // rejected by the license policy
+}
+
+const QUEUE_STATES: [QueueState; 8] = [
+    QueueState::Pending,
+    QueueState::InProgress,
+    QueueState::Done,
+    QueueState::FailedPermanent,
+    QueueState::FailedRetryable,
+    QueueState::SkippedBlacklist,
+    QueueState::SkippedNoLicense,
+    QueueState::SkippedLicense,
+];
+
+impl QueueState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QueueState::Pending => "pending",
+            QueueState::InProgress => "in-progress",
+            QueueState::Done => "done",
+            QueueState::FailedPermanent => "failed-permanent",
+            QueueState::FailedRetryable => "failed-retryable",
+            QueueState::SkippedBlacklist => "skipped-blacklist",
+            QueueState::SkippedNoLicense => "skipped-no-license",
+            QueueState::SkippedLicense => "skipped-license",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<QueueState> {
+        QUEUE_STATES.iter().cloned().find(|s| s.as_str() == name)
+    }
+}
+
+impl std::fmt::Display for QueueState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl rusqlite::types::ToSql for QueueState {
+    fn to_sql(&self) -> Result<rusqlite::types::ToSqlOutput<'_>, rusqlite::Error> {
+        Ok(rusqlite::types::ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl rusqlite::types::FromSql for QueueState {
+    fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Self> {
+        String::column_result(value).and_then(|s| {
+            QueueState::from_name(&s).ok_or(rusqlite::types::FromSqlError::InvalidType)
+        })
+    }
+}
+
+// Queued URL taken for processing, attempts includes this one.
+#[derive(Debug, PartialEq)]
+pub struct QueueItem {
+    pub id: i64,
+    pub action: String,
+    pub url: String,
+    pub attempts: u32,
+}
+
+pub struct QueueStatus {
+    pub url: String,
+    pub state: QueueState,
+    pub priority: i32,
+    pub attempts: u32,
+    pub not_before: Option<chrono::DateTime<chrono::Utc>>, // for failed-retryable
+    pub result: Option<String>,                            // None if it wasn't processed yet
+    pub result_date: Option<String>,
+}
+
+impl rusqlite::types::ToSql for BlacklistField {
+    fn to_sql(&self) -> Result<rusqlite::types::ToSqlOutput<'_>, rusqlite::Error> {
+        Ok(rusqlite::types::ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl rusqlite::types::FromSql for BlacklistField {
+    fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Self> {
+        String::column_result(value).and_then(|s| {
+            BlacklistField::from_name(&s).ok_or(rusqlite::types::FromSqlError::InvalidType)
+        })
+    }
+}
+
 impl rusqlite::types::ToSql for youtube::PlaylistID {
     fn to_sql(&self) -> Result<rusqlite::types::ToSqlOutput, rusqlite::Error> {
         Ok(rusqlite::types::ToSqlOutput::from(self.0.clone()))
@@ -38,62 +189,10 @@ impl rusqlite::types::FromSql for youtube::VideoID {
 
 impl Store {
     pub fn open(path: &Path) -> Result<Store, util::Error> {
-        let conn = rusqlite::Connection::open(path)?;
+        let mut conn = rusqlite::Connection::open(path)?;
 
         conn.pragma_update(None, "foreign_keys", &"on")?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS album (
-                id         INTEGER PRIMARY KEY,
-                url        TEXT UNIQUE NOT NULL,
-                artist     TEXT,
-                title      TEXT NOT NULL,
-                license    TEXT,
-                year       INTEGER,
-                labels     TEXT NOT NULL,
-                tags       TEXT NOT NULL,
-                youtube_id TEXT
-             )",
-            rusqlite::NO_PARAMS,
-        )?;
-
-        // AUTOINCREMENT is needed because we need the ids to be increasing to keep
-        // the tracks in their album order, see: https://www.sqlite.org/autoinc.html
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS track (
-                id         INTEGER PRIMARY KEY AUTOINCREMENT,
-                album_id   INTEGER NOT NULL REFERENCES album(id),
-                artist     TEXT NOT NULL,
-                title      TEXT NOT NULL,
-                bpm        INTEGER,
-                mp3_file   TEXT,
-                video_file TEXT,
-                youtube_id TEXT
-             )",
-            rusqlite::NO_PARAMS,
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS queue (
-                id          INTEGER PRIMARY KEY,
-                action      TEXT NOT NULL,
-                url         TEXT NOT NULL,
-                result      TEXT,
-                result_date TEXT,
-                UNIQUE      (url, action)
-            )",
-            rusqlite::NO_PARAMS,
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS blacklist (
-                id      INTEGER PRIMARY KEY,
-                type    TEXT NOT NULL CHECK(type IN ('artist', 'label')),
-                pattern TEXT NOT NULL,
-                UNIQUE  (type, pattern)
-            )",
-            rusqlite::NO_PARAMS,
-        )?;
+        migrate(&mut conn, path)?;
 
         log::debug!("Opened state file: {:?}", path);
         Ok(Store { conn: conn })
@@ -103,7 +202,8 @@ impl Store {
         let tx = self.conn.transaction()?;
 
         let mut stmt = tx.prepare(
-            "SELECT id, artist, title, license, year, labels, tags, youtube_id
+            "SELECT id, artist, title, license, year, labels, tags, youtube_id,
+                    release_date, description, download_url, album_video_id, notes
              FROM album
              WHERE url = ?1",
         )?;
@@ -120,6 +220,11 @@ impl Store {
                     tags: serde_json::from_value(row.get(6)?)?,
                     tracks: vec![],
                     youtube_id: row.get(7)?,
+                    release_date: row.get(8)?,
+                    description: row.get(9)?,
+                    download_url: row.get(10)?,
+                    album_video_id: row.get(11)?,
+                    notes: serde_json::from_value(row.get(12)?)?,
                 },
             ))
         })?;
@@ -130,10 +235,10 @@ impl Store {
         };
 
         let mut stmt = tx.prepare(
-            "SELECT artist, title, bpm, mp3_file, video_file, youtube_id
+            "SELECT artist, title, bpm, mp3_file, video_file, youtube_id, profile
              FROM track
              WHERE album_id = ?1
-             ORDER BY id",
+             ORDER BY number",
         )?;
         let it = stmt.query_map(&[album_id], |row| {
             Ok(Track {
@@ -143,6 +248,7 @@ impl Store {
                 mp3_file: row.get::<_, Option<String>>(3)?.map(|s| PathBuf::from(s)),
                 video_file: row.get::<_, Option<String>>(4)?.map(|s| PathBuf::from(s)),
                 youtube_id: row.get(5)?,
+                profile: row.get(6)?,
             })
         })?;
 
@@ -151,9 +257,26 @@ impl Store {
         Ok(Some(album))
     }
 
+    // Updates the rows in place so that album and track ids stay the same, tracks are
+    // identified by their number within the album.
     pub fn save(&mut self, album: &Album) -> Result<(), util::Error> {
         let tx = self.conn.transaction()?;
 
+        let album_params = params![
+            album.url,
+            album.artist,
+            album.title,
+            album.license,
+            album.year,
+            serde_json::to_value(&album.labels)?,
+            serde_json::to_value(&album.tags)?,
+            album.youtube_id,
+            album.release_date,
+            album.description,
+            album.download_url,
+            album.album_video_id,
+            serde_json::to_value(&album.notes)?,
+        ];
         let res: Option<i64> = tx
             .query_row(
                 "SELECT id FROM album WHERE url = ?1",
@@ -161,34 +284,46 @@ impl Store {
                 |row| row.get(0),
             )
             .optional()?;
-        if let Some(album_id) = res {
-            tx.execute("DELETE FROM track WHERE album_id = ?1", &[album_id])?;
-        }
+        let album_id = match res {
+            Some(album_id) => {
+                tx.execute(
+                    "UPDATE album
+                     SET artist = ?2, title = ?3, license = ?4, year = ?5, labels = ?6,
+                         tags = ?7, youtube_id = ?8, release_date = ?9, description = ?10,
+                         download_url = ?11, album_video_id = ?12, notes = ?13
+                     WHERE url = ?1",
+                    album_params,
+                )?;
+                album_id
+            }
+            None => {
+                tx.execute(
+                    "INSERT
+                     INTO album (url, artist, title, license, year, labels, tags, youtube_id,
+                                 release_date, description, download_url, album_video_id,
+                                 notes)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    album_params,
+                )?;
+                tx.last_insert_rowid()
+            }
+        };
 
-        tx.execute(
-            "INSERT OR REPLACE
-             INTO album (url, artist, title, license, year, labels, tags, youtube_id)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            params![
-                album.url,
-                album.artist,
-                album.title,
-                album.license,
-                album.year,
-                serde_json::to_value(&album.labels)?,
-                serde_json::to_value(&album.tags)?,
-                album.youtube_id
-            ],
+        let mut update = tx.prepare(
+            "UPDATE track
+             SET artist = ?3, title = ?4, bpm = ?5, mp3_file = ?6, video_file = ?7,
+                 youtube_id = ?8, profile = ?9
+             WHERE album_id = ?1 AND number = ?2",
         )?;
-        let album_id = tx.last_insert_rowid();
-
-        let mut stmt = tx.prepare(
-            "INSERT INTO track (album_id, artist, title, bpm, mp3_file, video_file, youtube_id)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        let mut insert = tx.prepare(
+            "INSERT INTO track (album_id, number, artist, title, bpm, mp3_file, video_file,
+                                youtube_id, profile)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
         )?;
-        for t in &album.tracks {
-            stmt.execute(params![
+        for (i, t) in album.tracks.iter().enumerate() {
+            let track_params = params![
                 album_id,
+                i as u32 + 1,
                 t.artist,
                 t.title,
                 t.bpm,
@@ -199,71 +334,701 @@ impl Store {
                     .as_ref()
                     .and_then(|f| f.to_str().map(|s| String::from(s))),
                 t.youtube_id,
-            ])?;
+                t.profile,
+            ];
+            if update.execute(track_params)? == 0 {
+                insert.execute(track_params)?;
+            }
         }
-        drop(stmt);
+        drop(update);
+        drop(insert);
+        tx.execute(
+            "DELETE FROM track WHERE album_id = ?1 AND number > ?2",
+            params![album_id, album.tracks.len() as u32],
+        )?;
+        tx.execute("DELETE FROM fetch_error WHERE url = ?1", params![album.url])?;
 
         tx.commit()?;
         Ok(())
     }
 
-    pub fn queue_insert(&mut self, url: &str) -> Result<(), util::Error> {
+    // Remembers why fetching the URL failed, until the album gets saved.
+    pub fn fetch_error_set(&mut self, url: &str, error: &str) -> Result<(), util::Error> {
         self.conn.execute(
-            "INSERT OR REPLACE
-             INTO queue (action, url)
-             VALUES ('url', ?1)",
-            params![url,],
+            "INSERT OR REPLACE INTO fetch_error (url, date, error) VALUES (?1, ?2, ?3)",
+            params![url, chrono::Utc::now(), error],
         )?;
         Ok(())
     }
 
-    pub fn queue_get(&mut self) -> Result<Option<(String, String)>, util::Error> {
+    pub fn fetch_error(
+        &mut self,
+        url: &str,
+    ) -> Result<Option<(chrono::DateTime<chrono::Utc>, String)>, util::Error> {
         let res = self
             .conn
             .query_row(
-                "SELECT action, url FROM queue WHERE result IS NULL ORDER BY id ASC LIMIT 1",
-                rusqlite::NO_PARAMS,
+                "SELECT date, error FROM fetch_error WHERE url = ?1",
+                params![url],
                 |row| Ok((row.get(0)?, row.get(1)?)),
             )
             .optional()?;
+        Ok(res)
+    }
+
+    // Never touches URLs that are already queued so that their results are kept. Returns
+    // whether the URL was added.
+    pub fn queue_insert(&mut self, url: &str, priority: i32) -> Result<bool, util::Error> {
+        let n = self.conn.execute(
+            "INSERT OR IGNORE
+             INTO queue (action, url, priority)
+             VALUES ('url', ?1, ?2)",
+            params![url, priority],
+        )?;
+        Ok(n > 0)
+    }
+
+    // Whether the URL is queued or the album was processed outside of the queue.
+    pub fn is_known(&mut self, url: &str) -> Result<bool, util::Error> {
+        let n: i64 = self.conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM album WHERE url = ?1)
+                  + (SELECT COUNT(*) FROM queue WHERE url = ?1)",
+            params![url],
+            |row| row.get(0),
+        )?;
+        Ok(n > 0)
+    }
+
+    // Picks the eligible item with the highest priority and marks it in-progress.
+    pub fn queue_get(
+        &mut self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<QueueItem>, util::Error> {
+        let tx = self.conn.transaction()?;
+        let res = tx
+            .query_row(
+                "SELECT id, action, url, attempts
+                 FROM queue
+                 WHERE state IN ('pending', 'failed-retryable')
+                   AND (not_before IS NULL OR not_before <= ?1)
+                 ORDER BY priority DESC, id ASC
+                 LIMIT 1",
+                params![now],
+                |row| {
+                    Ok(QueueItem {
+                        id: row.get(0)?,
+                        action: row.get(1)?,
+                        url: row.get(2)?,
+                        attempts: row.get::<_, u32>(3)? + 1,
+                    })
+                },
+            )
+            .optional()?;
+        if let Some(item) = &res {
+            tx.execute(
+                "UPDATE queue SET state = ?2, attempts = ?3 WHERE id = ?1",
+                params![item.id, QueueState::InProgress, item.attempts],
+            )?;
+        }
+        tx.commit()?;
 
         Ok(res)
     }
 
-    pub fn queue_result(
+    pub fn queue_finish(
         &mut self,
-        action: String,
-        url: String,
-        result: String,
+        id: i64,
+        state: QueueState,
+        result: &str,
+        not_before: Option<chrono::DateTime<chrono::Utc>>,
     ) -> Result<(), util::Error> {
         self.conn.execute(
             "UPDATE queue
-             SET result = ?1, result_date = ?2
-             WHERE url = ?3 AND action = ?4",
-            params![result, chrono::Local::now(), url, action,],
+             SET state = ?2, result = ?3, result_date = ?4, not_before = ?5
+             WHERE id = ?1",
+            params![id, state, result, chrono::Utc::now(), not_before],
         )?;
 
         Ok(())
     }
 
-    pub fn blacklist(&mut self) -> Result<Blacklist, util::Error> {
+    // Items left in-progress by a process that didn't finish them. Returns how many there were.
+    pub fn queue_recover(&mut self) -> Result<usize, util::Error> {
+        let n = self.conn.execute(
+            "UPDATE queue SET state = 'pending' WHERE state = 'in-progress'",
+            rusqlite::NO_PARAMS,
+        )?;
+        Ok(n)
+    }
+
+    // When the earliest item waiting for retry becomes eligible.
+    pub fn queue_next_retry(
+        &mut self,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, util::Error> {
+        let res = self.conn.query_row(
+            "SELECT MIN(not_before) FROM queue WHERE state = 'failed-retryable'",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        Ok(res)
+    }
+
+    // None if the URL is not queued.
+    pub fn queue_status(&mut self, url: &str) -> Result<Option<QueueStatus>, util::Error> {
+        let res = self
+            .conn
+            .query_row(
+                "SELECT url, state, priority, attempts, not_before, result, result_date
+                 FROM queue
+                 WHERE url = ?1 AND action = 'url'",
+                params![url],
+                queue_status_row,
+            )
+            .optional()?;
+
+        Ok(res)
+    }
+
+    // Queued URLs in the order they would be processed, all states if none are given.
+    pub fn queue_list(&mut self, states: &[QueueState]) -> Result<Vec<QueueStatus>, util::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT url, state, priority, attempts, not_before, result, result_date
+             FROM queue
+             WHERE action = 'url'
+             ORDER BY priority DESC, id ASC",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, queue_status_row)?;
+        let mut res = Vec::new();
+        for row in rows {
+            let st = row?;
+            if states.is_empty() || states.contains(&st.state) {
+                res.push(st);
+            }
+        }
+
+        Ok(res)
+    }
+
+    // Makes the URL eligible again with a fresh attempt counter. Returns false if it's not
+    // queued or is being processed right now.
+    pub fn queue_retry(&mut self, url: &str) -> Result<bool, util::Error> {
+        let n = self.conn.execute(
+            "UPDATE queue
+             SET state = 'pending', attempts = 0, not_before = NULL
+             WHERE url = ?1 AND action = 'url' AND state != 'in-progress'",
+            params![url],
+        )?;
+        Ok(n > 0)
+    }
+
+    // Same as queue_retry for all failed URLs, returns how many there were.
+    pub fn queue_retry_failed(&mut self) -> Result<usize, util::Error> {
+        let n = self.conn.execute(
+            "UPDATE queue
+             SET state = 'pending', attempts = 0, not_before = NULL
+             WHERE state IN ('failed-permanent', 'failed-retryable')",
+            rusqlite::NO_PARAMS,
+        )?;
+        Ok(n)
+    }
+
+    // After the license policy changed.
+    pub fn queue_retry_license(&mut self) -> Result<usize, util::Error> {
+        let n = self.conn.execute(
+            "UPDATE queue
+             SET state = 'pending', attempts = 0, not_before = NULL
+             WHERE state = 'skipped-license'",
+            rusqlite::NO_PARAMS,
+        )?;
+        Ok(n)
+    }
+
+    pub fn queue_remove(&mut self, url: &str) -> Result<bool, util::Error> {
+        let n = self.conn.execute(
+            "DELETE FROM queue WHERE url = ?1 AND action = 'url'",
+            params![url],
+        )?;
+        Ok(n > 0)
+    }
+
+    // Moves the URL in front of everything else that's queued.
+    pub fn queue_bump(&mut self, url: &str) -> Result<bool, util::Error> {
+        let n = self.conn.execute(
+            "UPDATE queue
+             SET priority = (SELECT MAX(priority) + 1 FROM queue)
+             WHERE url = ?1 AND action = 'url'",
+            params![url],
+        )?;
+        Ok(n > 0)
+    }
+
+    // Number of queued URLs in each state, including empty ones.
+    pub fn queue_stats(&mut self) -> Result<Vec<(QueueState, u32)>, util::Error> {
+        let mut res: Vec<(QueueState, u32)> = QUEUE_STATES.iter().map(|s| (*s, 0)).collect();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT state, COUNT(*) FROM queue GROUP BY state")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, QueueState>(0)?, row.get::<_, u32>(1)?))
+        })?;
+        for row in rows {
+            let (state, n) = row?;
+            if let Some(entry) = res.iter_mut().find(|(s, _)| *s == state) {
+                entry.1 = n;
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub fn upload_event_insert(&mut self, ev: &UploadEvent) -> Result<(), util::Error> {
+        self.conn.execute(
+            "INSERT INTO upload_event (kind, album_id, track_id, started, finished, bytes,
+                                       attempts, outcome, youtube_id, error)
+             SELECT ?1, album.id, track.id, ?4, ?5, ?6, ?7, ?8, ?9, ?10
+             FROM album LEFT JOIN track ON track.album_id = album.id AND track.number = ?3
+             WHERE album.url = ?2",
+            params![
+                ev.kind.as_str(),
+                ev.album_url,
+                ev.track,
+                ev.started,
+                ev.finished,
+                ev.bytes.map(|b| b as i64),
+                ev.attempts,
+                if ev.error.is_none() { "ok" } else { "failed" },
+                ev.youtube_id,
+                ev.error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Events that started in the [since, until) interval, optionally only for one album.
+    pub fn upload_history(
+        &mut self,
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+        album_url: Option<&str>,
+    ) -> Result<Vec<UploadEvent>, util::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT e.kind, album.url, track.number, e.started, e.finished, e.bytes,
+                    e.attempts, e.youtube_id, e.error
+             FROM upload_event e
+             JOIN album ON album.id = e.album_id
+             LEFT JOIN track ON track.id = e.track_id
+             WHERE (?1 IS NULL OR e.started >= ?1)
+               AND (?2 IS NULL OR e.started < ?2)
+               AND (?3 IS NULL OR album.url = ?3)
+             ORDER BY e.started, e.id",
+        )?;
+        let it = stmt.query_map(params![since, until, album_url], |row| {
+            Ok(UploadEvent {
+                kind: row.get(0)?,
+                album_url: row.get(1)?,
+                track: row.get(2)?,
+                started: row.get(3)?,
+                finished: row.get(4)?,
+                bytes: row.get::<_, Option<i64>>(5)?.map(|b| b as u64),
+                attempts: row.get(6)?,
+                youtube_id: row.get(7)?,
+                error: row.get(8)?,
+            })
+        })?;
+
+        Ok(it.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // In the order they were added.
+    pub fn blacklist_rules(&mut self) -> Result<Vec<BlacklistRule>, util::Error> {
         let mut stmt = self.conn.prepare(
-            "SELECT pattern
+            "SELECT type, pattern, allow
              FROM blacklist
-             WHERE type = ?1",
+             ORDER BY id",
         )?;
+        let it = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok(BlacklistRule {
+                field: row.get(0)?,
+                pattern: row.get(1)?,
+                allow: row.get(2)?,
+            })
+        })?;
+
+        Ok(it.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    pub fn blacklist(&mut self) -> Result<Blacklist, util::Error> {
+        Ok(Blacklist::new(self.blacklist_rules()?)?)
+    }
 
-        let artists: Vec<String> = stmt
-            .query_map(&["artist"], |row| row.get(0))?
-            .collect::<Result<_, _>>()?;
-        let labels: Vec<String> = stmt
-            .query_map(&["label"], |row| row.get(0))?
-            .collect::<Result<_, _>>()?;
+    // Returns false if the rule already exists. Invalid patterns are rejected.
+    pub fn blacklist_add(&mut self, rule: &BlacklistRule) -> Result<bool, util::Error> {
+        Blacklist::new(vec![rule.clone()])?;
+        let n = self.conn.execute(
+            "INSERT OR IGNORE
+             INTO blacklist (type, pattern, allow)
+             VALUES (?1, ?2, ?3)",
+            params![rule.field, rule.pattern, rule.allow],
+        )?;
+        Ok(n > 0)
+    }
 
-        Ok(Blacklist::new(artists, labels)?)
+    pub fn blacklist_remove(&mut self, rule: &BlacklistRule) -> Result<bool, util::Error> {
+        let n = self.conn.execute(
+            "DELETE FROM blacklist WHERE type = ?1 AND pattern = ?2 AND allow = ?3",
+            params![rule.field, rule.pattern, rule.allow],
+        )?;
+        Ok(n > 0)
     }
 }
 
+fn queue_status_row(row: &rusqlite::Row) -> Result<QueueStatus, rusqlite::Error> {
+    Ok(QueueStatus {
+        url: row.get(0)?,
+        state: row.get(1)?,
+        priority: row.get(2)?,
+        attempts: row.get(3)?,
+        not_before: row.get(4)?,
+        result: row.get(5)?,
+        result_date: row.get(6)?,
+    })
+}
+
+type Migration = fn(&rusqlite::Connection) -> Result<(), util::Error>;
+
+// Step i upgrades the schema from version i to i + 1, the version is stored in user_version.
+// Only ever append to this list, state files in the wild have the old steps applied already.
+const MIGRATIONS: &[Migration] = &[
+    migrate_initial,
+    migrate_album_web_metadata,
+    migrate_track_number,
+    migrate_upload_event,
+    migrate_queue_state,
+    migrate_blacklist_rules,
+    migrate_track_profile,
+    migrate_album_video,
+    migrate_fetch_notes,
+    migrate_queue_skipped_license,
+    migrate_upload_album_video,
+];
+
+fn schema_version(conn: &rusqlite::Connection) -> Result<usize, util::Error> {
+    let v: i64 = conn.query_row("PRAGMA user_version", rusqlite::NO_PARAMS, |row| row.get(0))?;
+    Ok(v as usize)
+}
+
+fn migrate(conn: &mut rusqlite::Connection, path: &Path) -> Result<(), util::Error> {
+    let version = schema_version(conn)?;
+    if version > MIGRATIONS.len() {
+        return Err(util::Error::new(&format!(
+            "State file {:?} has schema version {} but this version of ektoboat only knows {}",
+            path,
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    if tables > 0 {
+        let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+        log::info!(
+            "Upgrading state file from version {} to {}, backup in {:?}",
+            version,
+            MIGRATIONS.len(),
+            backup
+        );
+        std::fs::copy(path, &backup)?;
+    }
+
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        step(&tx)?;
+        tx.pragma_update(None, "user_version", &(i as i64 + 1))?;
+        tx.commit()?;
+        log::debug!("Migrated state file to version {}", i + 1);
+    }
+    Ok(())
+}
+
+// Tables as they were before versioning, IF NOT EXISTS because unversioned state files have them.
+fn migrate_initial(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS album (
+            id         INTEGER PRIMARY KEY,
+            url        TEXT UNIQUE NOT NULL,
+            artist     TEXT,
+            title      TEXT NOT NULL,
+            license    TEXT,
+            year       INTEGER,
+            labels     TEXT NOT NULL,
+            tags       TEXT NOT NULL,
+            youtube_id TEXT
+         )",
+        rusqlite::NO_PARAMS,
+    )?;
+
+    // AUTOINCREMENT is needed because we need the ids to be increasing to keep
+    // the tracks in their album order, see: https://www.sqlite.org/autoinc.html
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS track (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            album_id   INTEGER NOT NULL REFERENCES album(id),
+            artist     TEXT NOT NULL,
+            title      TEXT NOT NULL,
+            bpm        INTEGER,
+            mp3_file   TEXT,
+            video_file TEXT,
+            youtube_id TEXT
+         )",
+        rusqlite::NO_PARAMS,
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS queue (
+            id          INTEGER PRIMARY KEY,
+            action      TEXT NOT NULL,
+            url         TEXT NOT NULL,
+            result      TEXT,
+            result_date TEXT,
+            UNIQUE      (url, action)
+        )",
+        rusqlite::NO_PARAMS,
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blacklist (
+            id      INTEGER PRIMARY KEY,
+            type    TEXT NOT NULL CHECK(type IN ('artist', 'label')),
+            pattern TEXT NOT NULL,
+            UNIQUE  (type, pattern)
+        )",
+        rusqlite::NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+// Unversioned state files may already have these.
+fn migrate_album_web_metadata(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    add_column(conn, "album", "release_date", "TEXT")?;
+    add_column(conn, "album", "description", "TEXT")?;
+    add_column(conn, "album", "download_url", "TEXT")?;
+    Ok(())
+}
+
+// Tracks used to be ordered by id and rewritten on every save.
+fn migrate_track_number(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute_batch(
+        "CREATE TABLE track_new (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            album_id   INTEGER NOT NULL REFERENCES album(id),
+            number     INTEGER NOT NULL,
+            artist     TEXT NOT NULL,
+            title      TEXT NOT NULL,
+            bpm        INTEGER,
+            mp3_file   TEXT,
+            video_file TEXT,
+            youtube_id TEXT,
+            UNIQUE     (album_id, number)
+         );
+         INSERT INTO track_new (id, album_id, number, artist, title, bpm, mp3_file, video_file,
+                                youtube_id)
+         SELECT id, album_id,
+                (SELECT COUNT(*) FROM track t2 WHERE t2.album_id = t.album_id AND t2.id <= t.id),
+                artist, title, bpm, mp3_file, video_file, youtube_id
+         FROM track t;
+         DROP TABLE track;
+         ALTER TABLE track_new RENAME TO track;",
+    )?;
+    Ok(())
+}
+
+fn migrate_upload_event(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute(
+        "CREATE TABLE upload_event (
+            id         INTEGER PRIMARY KEY,
+            kind       TEXT NOT NULL CHECK(kind IN ('video', 'playlist')),
+            album_id   INTEGER NOT NULL REFERENCES album(id),
+            track_id   INTEGER REFERENCES track(id) ON DELETE SET NULL,
+            started    TEXT NOT NULL,
+            finished   TEXT NOT NULL,
+            bytes      INTEGER,
+            attempts   INTEGER NOT NULL,
+            outcome    TEXT NOT NULL CHECK(outcome IN ('ok', 'failed')),
+            youtube_id TEXT,
+            error      TEXT
+         )",
+        rusqlite::NO_PARAMS,
+    )?;
+    conn.execute(
+        "CREATE INDEX upload_event_started ON upload_event(started)",
+        rusqlite::NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+// Results used to be free text, NULL meaning pending.
+fn migrate_queue_state(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute_batch(
+        "ALTER TABLE queue ADD COLUMN state TEXT NOT NULL DEFAULT 'pending'
+            CHECK(state IN ('pending', 'in-progress', 'done', 'failed-permanent',
+                            'failed-retryable', 'skipped-blacklist', 'skipped-no-license'));
+         ALTER TABLE queue ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE queue ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE queue ADD COLUMN not_before TEXT;
+         UPDATE queue
+         SET state = CASE
+                 WHEN result IS NULL THEN 'pending'
+                 WHEN result = 'OK' THEN 'done'
+                 WHEN result = 'Blacklisted' THEN 'skipped-blacklist'
+                 WHEN result = 'No license' THEN 'skipped-no-license'
+                 ELSE 'failed-permanent'
+             END,
+             attempts = CASE WHEN result IS NULL THEN 0 ELSE 1 END;
+         CREATE INDEX queue_state_priority ON queue(state, priority);",
+    )?;
+    Ok(())
+}
+
+// SQLite can't alter CHECK constraints so the table has to be rebuilt. Nothing references it.
+fn migrate_blacklist_rules(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute_batch(
+        "CREATE TABLE blacklist_new (
+            id      INTEGER PRIMARY KEY,
+            type    TEXT NOT NULL
+                    CHECK(type IN ('artist', 'label', 'tag', 'album-title', 'url')),
+            pattern TEXT NOT NULL,
+            allow   INTEGER NOT NULL DEFAULT 0 CHECK(allow IN (0, 1)),
+            UNIQUE  (type, pattern, allow)
+         );
+         INSERT INTO blacklist_new (id, type, pattern) SELECT id, type, pattern FROM blacklist;
+         DROP TABLE blacklist;
+         ALTER TABLE blacklist_new RENAME TO blacklist;",
+    )?;
+    Ok(())
+}
+
+fn migrate_track_profile(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    add_column(conn, "track", "profile", "TEXT")
+}
+
+fn migrate_album_video(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    add_column(conn, "album", "album_video_id", "TEXT")
+}
+
+// Tracklist differences of fetched albums, and why the last fetch failed for the rejected ones.
+fn migrate_fetch_notes(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    add_column(conn, "album", "notes", "TEXT NOT NULL DEFAULT '[]'")?;
+    conn.execute(
+        "CREATE TABLE fetch_error (
+            url   TEXT PRIMARY KEY,
+            date  TEXT NOT NULL,
+            error TEXT NOT NULL
+         )",
+        rusqlite::NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+// Policy rejections used to be recorded as skipped-no-license, their result is the reason
+// while albums without license have "No license".
+fn migrate_queue_skipped_license(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute_batch(
+        "CREATE TABLE queue_new (
+            id          INTEGER PRIMARY KEY,
+            action      TEXT NOT NULL,
+            url         TEXT NOT NULL,
+            result      TEXT,
+            result_date TEXT,
+            state       TEXT NOT NULL DEFAULT 'pending'
+                        CHECK(state IN ('pending', 'in-progress', 'done', 'failed-permanent',
+                                        'failed-retryable', 'skipped-blacklist',
+                                        'skipped-no-license', 'skipped-license')),
+            priority    INTEGER NOT NULL DEFAULT 0,
+            attempts    INTEGER NOT NULL DEFAULT 0,
+            not_before  TEXT,
+            UNIQUE      (url, action)
+         );
+         INSERT INTO queue_new (id, action, url, result, result_date, state, priority, attempts,
+                                not_before)
+         SELECT id, action, url, result, result_date,
+                CASE
+                    WHEN state = 'skipped-no-license' AND result != 'No license'
+                    THEN 'skipped-license'
+                    ELSE state
+                END,
+                priority, attempts, not_before
+         FROM queue;
+         DROP TABLE queue;
+         ALTER TABLE queue_new RENAME TO queue;
+         CREATE INDEX queue_state_priority ON queue(state, priority);",
+    )?;
+    Ok(())
+}
+
+// Album videos used to be logged as videos without track, recognized by their YouTube id.
+fn migrate_upload_album_video(conn: &rusqlite::Connection) -> Result<(), util::Error> {
+    conn.execute_batch(
+        "CREATE TABLE upload_event_new (
+            id         INTEGER PRIMARY KEY,
+            kind       TEXT NOT NULL CHECK(kind IN ('video', 'playlist', 'album-video')),
+            album_id   INTEGER NOT NULL REFERENCES album(id),
+            track_id   INTEGER REFERENCES track(id) ON DELETE SET NULL,
+            started    TEXT NOT NULL,
+            finished   TEXT NOT NULL,
+            bytes      INTEGER,
+            attempts   INTEGER NOT NULL,
+            outcome    TEXT NOT NULL CHECK(outcome IN ('ok', 'failed')),
+            youtube_id TEXT,
+            error      TEXT
+         );
+         INSERT INTO upload_event_new
+         SELECT e.id,
+                CASE
+                    WHEN e.kind = 'video' AND e.track_id IS NULL
+                         AND e.youtube_id = album.album_video_id
+                    THEN 'album-video'
+                    ELSE e.kind
+                END,
+                e.album_id, e.track_id, e.started, e.finished, e.bytes, e.attempts, e.outcome,
+                e.youtube_id, e.error
+         FROM upload_event e
+         LEFT JOIN album ON album.id = e.album_id;
+         DROP TABLE upload_event;
+         ALTER TABLE upload_event_new RENAME TO upload_event;
+         CREATE INDEX upload_event_started ON upload_event(started);",
+    )?;
+    Ok(())
+}
+
+fn add_column(
+    conn: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> Result<(), util::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|c| c == column);
+    if !exists {
+        log::info!("Adding column {}.{}", table, column);
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            rusqlite::NO_PARAMS,
+        )?;
+    }
+    Ok(())
+}
+
 fn at_most_one<T: Iterator>(mut it: T) -> Result<Option<T::Item>, util::Error> {
     let first = match it.next() {
         None => return Ok(None),
@@ -282,6 +1047,7 @@ mod tests {
     use super::*;
     use crate::model::{Album, Track};
     use crate::youtube;
+    use chrono::TimeZone;
     use tempfile;
 
     #[test]
@@ -312,11 +1078,24 @@ mod tests {
                 bpm: Some(666),
                 mp3_file: None,
                 video_file: None,
+                profile: None,
                 youtube_id: Some(youtube::VideoID("asdf".to_string())),
             }],
             youtube_id: Some(youtube::PlaylistID("PL0123".to_string())),
+            release_date: Some(chrono::NaiveDate::from_ymd(2018, 12, 29)),
+            description: Some(
+                "An eclectic ride through psychedelic downtempo and dub.".to_string(),
+            ),
+            download_url: None,
+            album_video_id: Some(youtube::VideoID("full".to_string())),
+            notes: vec!["track 01 BPM 666 taken from the web".to_string()],
         };
+        store
+            .fetch_error_set(album_url, "Tracklist on the web does not match the files")
+            .unwrap();
+        assert!(store.fetch_error(album_url).unwrap().is_some());
         store.save(&album).unwrap();
+        assert_eq!(store.fetch_error(album_url).unwrap(), None);
         let a = store.get_album(album_url).unwrap();
         assert_eq!(album, a.unwrap());
         let b = store.get_album(album_url).unwrap();
@@ -334,10 +1113,488 @@ mod tests {
             bpm: Some(1),
             mp3_file: Some(PathBuf::from("/tmp/2.mp3")),
             video_file: Some(PathBuf::from("/tmp/2.avi")),
+            profile: Some("youtube-mp4".to_string()),
             youtube_id: Some(youtube::VideoID("3e4nQTFhieo".to_string())),
         });
         store.save(&album).unwrap();
         let d = store.get_album(album_url).unwrap();
         assert_eq!(album, d.unwrap());
     }
+
+    #[test]
+    fn add_missing_columns() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let conn = rusqlite::Connection::open(tmp.path()).unwrap();
+        conn.execute(
+            "CREATE TABLE album (
+                id         INTEGER PRIMARY KEY,
+                url        TEXT UNIQUE NOT NULL,
+                artist     TEXT,
+                title      TEXT NOT NULL,
+                license    TEXT,
+                year       INTEGER,
+                labels     TEXT NOT NULL,
+                tags       TEXT NOT NULL,
+                youtube_id TEXT
+             )",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO album (url, title, labels, tags) VALUES ('file:///a', 'A', '[]', '[]')",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut store = Store::open(tmp.path()).unwrap();
+        let mut album = store.get_album("file:///a").unwrap().unwrap();
+        assert_eq!(album.release_date, None);
+        album.download_url = Some("file:///a.zip".to_string());
+        store.save(&album).unwrap();
+        drop(store);
+
+        let mut store = Store::open(tmp.path()).unwrap();
+        assert_eq!(store.get_album("file:///a").unwrap().unwrap(), album);
+    }
+
+    #[test]
+    fn queue_status() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let url = "https://ektoplazm.com/free-music/globular-entangled-everything";
+
+        assert!(store.queue_status(url).unwrap().is_none());
+        assert!(!store.is_known(url).unwrap());
+        assert!(store.queue_insert(url, 0).unwrap());
+        assert!(store.is_known(url).unwrap());
+        let st = store.queue_status(url).unwrap().unwrap();
+        assert_eq!(st.state, QueueState::Pending);
+        assert_eq!(st.result, None);
+        assert_eq!(st.result_date, None);
+
+        let item = store.queue_get(chrono::Utc::now()).unwrap().unwrap();
+        assert_eq!(item.url, url);
+        assert_eq!(item.attempts, 1);
+        assert_eq!(
+            store.queue_status(url).unwrap().unwrap().state,
+            QueueState::InProgress
+        );
+        let diff = "Tracklist on the web does not match the files:\n! 01 A / B\n";
+        store
+            .queue_finish(item.id, QueueState::FailedPermanent, diff, None)
+            .unwrap();
+        let st = store.queue_status(url).unwrap().unwrap();
+        assert_eq!(st.state, QueueState::FailedPermanent);
+        assert_eq!(st.result, Some(diff.to_string()));
+        assert!(st.result_date.is_some());
+        assert_eq!(store.queue_get(chrono::Utc::now()).unwrap(), None);
+
+        // rescraping must not reset the result
+        assert!(!store.queue_insert(url, 0).unwrap());
+        let st = store.queue_status(url).unwrap().unwrap();
+        assert_eq!(st.result, Some(diff.to_string()));
+    }
+
+    #[test]
+    fn queue_scheduling() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let now = chrono::Utc.ymd(2020, 6, 1).and_hms(12, 0, 0);
+        store.queue_insert("a", 0).unwrap();
+        store.queue_insert("b", 0).unwrap();
+        store.queue_insert("c", 5).unwrap();
+
+        // highest priority first, then in insertion order
+        let c = store.queue_get(now).unwrap().unwrap();
+        assert_eq!(c.url, "c");
+        let a = store.queue_get(now).unwrap().unwrap();
+        assert_eq!(a.url, "a");
+
+        // backoff
+        let later = now + chrono::Duration::hours(1);
+        store
+            .queue_finish(c.id, QueueState::FailedRetryable, "503", Some(later))
+            .unwrap();
+        assert_eq!(store.queue_next_retry().unwrap(), Some(later));
+        assert_eq!(store.queue_get(now).unwrap().unwrap().url, "b");
+        assert_eq!(store.queue_get(now).unwrap(), None);
+        let c = store.queue_get(later).unwrap().unwrap();
+        assert_eq!(c.url, "c");
+        assert_eq!(c.attempts, 2);
+
+        // a, b and c were left in-progress
+        assert_eq!(store.queue_recover().unwrap(), 3);
+        assert_eq!(store.queue_get(later).unwrap().unwrap().url, "c");
+    }
+
+    #[test]
+    fn queue_management() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let now = chrono::Utc::now();
+        for url in &["a", "b", "c"] {
+            store.queue_insert(url, 0).unwrap();
+        }
+        let urls = |l: Vec<QueueStatus>| l.into_iter().map(|s| s.url).collect::<Vec<_>>();
+
+        assert!(store.queue_bump("c").unwrap());
+        assert!(!store.queue_bump("x").unwrap());
+        assert_eq!(urls(store.queue_list(&[]).unwrap()), vec!["c", "a", "b"]);
+
+        let c = store.queue_get(now).unwrap().unwrap();
+        store
+            .queue_finish(c.id, QueueState::FailedPermanent, "404", None)
+            .unwrap();
+        let a = store.queue_get(now).unwrap().unwrap();
+        assert!(!store.queue_retry("a").unwrap());
+        store
+            .queue_finish(a.id, QueueState::Done, "OK", None)
+            .unwrap();
+        let b = store.queue_get(now).unwrap().unwrap();
+        store
+            .queue_finish(
+                b.id,
+                QueueState::SkippedLicense,
+                "License not allowed",
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            urls(
+                store
+                    .queue_list(&[QueueState::FailedPermanent, QueueState::Done])
+                    .unwrap()
+            ),
+            vec!["c", "a"]
+        );
+        let stats = store.queue_stats().unwrap();
+        assert_eq!(stats.len(), QUEUE_STATES.len());
+        assert!(stats.contains(&(QueueState::Pending, 0)));
+        assert!(stats.contains(&(QueueState::Done, 1)));
+        assert!(stats.contains(&(QueueState::SkippedLicense, 1)));
+        assert!(stats.contains(&(QueueState::InProgress, 0)));
+
+        assert_eq!(store.queue_retry_failed().unwrap(), 1);
+        assert_eq!(store.queue_retry_license().unwrap(), 1);
+        assert_eq!(
+            store.queue_status("b").unwrap().unwrap().state,
+            QueueState::Pending
+        );
+        let st = store.queue_status("c").unwrap().unwrap();
+        assert_eq!(st.state, QueueState::Pending);
+        assert_eq!(st.attempts, 0);
+        assert!(store.queue_retry("a").unwrap());
+
+        assert!(store.queue_remove("b").unwrap());
+        assert!(!store.queue_remove("b").unwrap());
+        assert_eq!(urls(store.queue_list(&[]).unwrap()), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn blacklist_rules() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let rules = vec![
+            BlacklistRule::deny(BlacklistField::AlbumTitle, ".*christmas.*"),
+            BlacklistRule::deny(BlacklistField::Tag, "goa"),
+            BlacklistRule::allow(BlacklistField::Tag, "goa"),
+        ];
+        for rule in &rules {
+            assert!(store.blacklist_add(rule).unwrap());
+        }
+        assert!(!store.blacklist_add(&rules[1]).unwrap());
+        assert!(store
+            .blacklist_add(&BlacklistRule::deny(BlacklistField::Url, "[a-"))
+            .is_err());
+        assert_eq!(store.blacklist_rules().unwrap(), rules);
+
+        assert!(store.blacklist_remove(&rules[2]).unwrap());
+        assert!(!store.blacklist_remove(&rules[2]).unwrap());
+        assert_eq!(store.blacklist_rules().unwrap(), &rules[..2]);
+    }
+
+    // Creates state file from SQL dump in tests/data.
+    fn fixture_db(fname: &str, path: &Path) {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("tests");
+        d.push("data");
+        d.push(fname);
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute_batch(&std::fs::read_to_string(d).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn upgrade_fixtures() {
+        let album_url = "https://ektoplazm.com/free-music/globular-entangled-everything";
+        for fname in &["store-v0.sql", "store-v0-web.sql"] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("state.sqlite3");
+            fixture_db(fname, &path);
+
+            let mut store = Store::open(&path).unwrap();
+            assert_eq!(schema_version(&store.conn).unwrap(), MIGRATIONS.len());
+            assert!(dir.path().join("state.sqlite3.v0.bak").is_file());
+
+            let mut album = store.get_album(album_url).unwrap().unwrap();
+            assert_eq!(album.title, "Entangled Everything");
+            assert_eq!(
+                album.youtube_id,
+                Some(youtube::PlaylistID("PL0123".to_string()))
+            );
+            assert_eq!(album.tracks.len(), 2);
+            assert_eq!(
+                album.tracks[0].youtube_id,
+                Some(youtube::VideoID("aaaaaaaaaaa".to_string()))
+            );
+            assert_eq!(album.tracks[1].bpm, Some(65));
+            if *fname == "store-v0-web.sql" {
+                assert_eq!(
+                    album.release_date,
+                    Some(chrono::NaiveDate::from_ymd(2018, 12, 29))
+                );
+            } else {
+                assert_eq!(album.release_date, None);
+            }
+            let st = store.queue_status(album_url).unwrap().unwrap();
+            assert_eq!(st.result, Some("OK".to_string()));
+            assert_eq!(st.state, QueueState::Done);
+            assert_eq!(st.attempts, 1);
+            let st = store
+                .queue_status("https://ektoplazm.com/free-music/white-morph-dream-catcher")
+                .unwrap()
+                .unwrap();
+            assert_eq!(st.state, QueueState::Pending);
+            assert_eq!(st.attempts, 0);
+            let blacklisted = Album {
+                labels: vec!["Sony".to_string()],
+                ..album.clone()
+            };
+            assert!(matches!(
+                store.blacklist().unwrap().explain(&blacklisted),
+                crate::model::Verdict::Denied(_)
+            ));
+            assert_eq!(
+                store.blacklist_rules().unwrap(),
+                vec![
+                    BlacklistRule::deny(BlacklistField::Artist, "donald duck"),
+                    BlacklistRule::deny(BlacklistField::Label, "sony"),
+                ]
+            );
+
+            album.download_url = Some("file:///tmp/a.zip".to_string());
+            store.save(&album).unwrap();
+            drop(store);
+
+            // already up to date, no more backups
+            let mut store = Store::open(&path).unwrap();
+            assert_eq!(store.get_album(album_url).unwrap().unwrap(), album);
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+        }
+    }
+
+    #[test]
+    fn new_and_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite3");
+
+        let store = Store::open(&path).unwrap();
+        assert_eq!(schema_version(&store.conn).unwrap(), MIGRATIONS.len());
+        drop(store);
+        // nothing to back up
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.pragma_update(None, "user_version", &(MIGRATIONS.len() as i64 + 1))
+            .unwrap();
+        drop(conn);
+        let err = Store::open(&path).err().unwrap();
+        assert!(err.to_string().contains("has schema version"));
+    }
+
+    #[test]
+    fn stable_track_ids() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let track = |title: &str| Track {
+            artist: "Globular".to_string(),
+            title: title.to_string(),
+            bpm: None,
+            mp3_file: None,
+            video_file: None,
+            profile: None,
+            youtube_id: None,
+        };
+        let mut album = Album {
+            url: "https://ektoplazm.com/free-music/globular-entangled-everything".to_string(),
+            artist: Some("Globular".to_string()),
+            title: "Entangled Everything".to_string(),
+            license: None,
+            year: Some(2018),
+            labels: vec![],
+            tags: vec![],
+            tracks: vec![
+                track("Popping Out"),
+                track("For The Time Being"),
+                track("The Chalice"),
+            ],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        let ids = |store: &Store| -> Vec<(i64, u32, String)> {
+            let mut stmt = store
+                .conn
+                .prepare("SELECT id, number, title FROM track ORDER BY number")
+                .unwrap();
+            stmt.query_map(rusqlite::NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+        };
+
+        store.save(&album).unwrap();
+        let before = ids(&store);
+        let album_id: i64 = store
+            .conn
+            .query_row("SELECT id FROM album", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+
+        album.tracks[1].youtube_id = Some(youtube::VideoID("bbb".to_string()));
+        album.youtube_id = Some(youtube::PlaylistID("PL0123".to_string()));
+        store.save(&album).unwrap();
+        assert_eq!(ids(&store), before);
+        assert_eq!(
+            store
+                .conn
+                .query_row("SELECT id FROM album", rusqlite::NO_PARAMS, |row| row
+                    .get::<_, i64>(0))
+                .unwrap(),
+            album_id
+        );
+        assert_eq!(store.get_album(&album.url).unwrap().unwrap(), album);
+
+        album.tracks.pop();
+        album.tracks[0].title = "Popping Out (Edit)".to_string();
+        store.save(&album).unwrap();
+        let after = ids(&store);
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0], (before[0].0, 1, "Popping Out (Edit)".to_string()));
+        assert_eq!(after[1], before[1]);
+
+        album.tracks.push(track("Dasein"));
+        store.save(&album).unwrap();
+        let after = ids(&store);
+        assert_eq!(after[2].1, 3);
+        assert!(after[2].0 > before[2].0);
+        assert_eq!(store.get_album(&album.url).unwrap().unwrap(), album);
+    }
+
+    #[test]
+    fn upload_history() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut store = Store::open(tmp.path()).unwrap();
+        let album = |url: &str| Album {
+            url: url.to_string(),
+            artist: None,
+            title: "Dividing 2 Worlds".to_string(),
+            license: None,
+            year: None,
+            labels: vec![],
+            tags: vec![],
+            tracks: vec![Track {
+                artist: "Crennwiick".to_string(),
+                title: "Scape".to_string(),
+                bpm: None,
+                mp3_file: None,
+                video_file: None,
+                profile: None,
+                youtube_id: None,
+            }],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        let url_a = "https://ektoplazm.com/free-music/va-dividing-2-worlds";
+        let url_b = "file:///music/va";
+        store.save(&album(url_a)).unwrap();
+        store.save(&album(url_b)).unwrap();
+
+        let t = |day: u32, hour: u32| chrono::Utc.ymd(2020, 5, day).and_hms(hour, 0, 0);
+        let ev = |url: &str, track: Option<u32>, started, error: Option<&str>| UploadEvent {
+            kind: if track.is_some() {
+                UploadKind::Video
+            } else {
+                UploadKind::Playlist
+            },
+            album_url: url.to_string(),
+            track,
+            started,
+            finished: started + chrono::Duration::seconds(90),
+            bytes: track.map(|_| 12_345_678),
+            attempts: if error.is_some() { 9 } else { 1 },
+            youtube_id: if error.is_some() {
+                None
+            } else {
+                Some("3e4nQTFhieo".to_string())
+            },
+            error: error.map(String::from),
+        };
+        let events = vec![
+            ev(url_a, Some(1), t(1, 10), Some("quota exceeded")),
+            ev(url_a, Some(1), t(2, 10), None),
+            ev(url_a, None, t(2, 11), None),
+            ev(url_b, Some(1), t(3, 10), None),
+        ];
+        for e in &events {
+            store.upload_event_insert(e).unwrap();
+        }
+
+        assert_eq!(store.upload_history(None, None, None).unwrap(), events);
+        assert_eq!(
+            store.upload_history(None, None, Some(url_a)).unwrap(),
+            &events[0..3]
+        );
+        assert_eq!(
+            store
+                .upload_history(Some(t(2, 0)), Some(t(3, 0)), None)
+                .unwrap(),
+            &events[1..3]
+        );
+        assert_eq!(
+            store
+                .upload_history(Some(t(2, 11)), None, Some(url_b))
+                .unwrap(),
+            &events[3..]
+        );
+
+        let album_video = UploadEvent {
+            kind: UploadKind::AlbumVideo,
+            ..ev(url_b, None, t(4, 10), None)
+        };
+        store.upload_event_insert(&album_video).unwrap();
+        assert_eq!(
+            store.upload_history(Some(t(4, 0)), None, None).unwrap(),
+            vec![album_video]
+        );
+
+        // track disappearing from the album keeps the history
+        let mut a = album(url_a);
+        a.tracks.clear();
+        store.save(&a).unwrap();
+        let history = store.upload_history(None, None, Some(url_a)).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].track, None);
+    }
 }
@@ -0,0 +1,728 @@
+use crate::license;
+use crate::model::{Album, Track};
+use crate::util;
+use crate::youtube;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Mustache-like templates for YouTube metadata:
+//   {{name}}                   value, dotted names like {{album.title}} look into maps
+//   {{#name}}...{{/name}}      rendered if the value is non-empty, once for each item of a list
+//   {{^name}}...{{/name}}      rendered if the value is missing or empty
+//   {{! comment }}
+// Lines containing nothing but a section or comment tag are dropped entirely. No escaping is
+// done, the output is plain text.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplateKind {
+    VideoTitle,
+    VideoDescription,
+    PlaylistTitle,
+    PlaylistDescription,
+    AlbumVideoTitle,
+    AlbumVideoDescription,
+}
+
+impl TemplateKind {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            TemplateKind::VideoTitle => "video-title.txt",
+            TemplateKind::VideoDescription => "video-description.txt",
+            TemplateKind::PlaylistTitle => "playlist-title.txt",
+            TemplateKind::PlaylistDescription => "playlist-description.txt",
+            TemplateKind::AlbumVideoTitle => "album-video-title.txt",
+            TemplateKind::AlbumVideoDescription => "album-video-description.txt",
+        }
+    }
+
+    // Built-in template, sources may add to it.
+    pub fn default_source(self) -> &'static str {
+        match self {
+            TemplateKind::VideoTitle => "{{track.artist}} - {{track.title}}",
+            TemplateKind::VideoDescription => DEFAULT_VIDEO_DESCRIPTION,
+            TemplateKind::PlaylistTitle => "{{album.playlist_title}}",
+            TemplateKind::PlaylistDescription => DEFAULT_PLAYLIST_DESCRIPTION,
+            TemplateKind::AlbumVideoTitle => "{{album.playlist_title}}",
+            TemplateKind::AlbumVideoDescription => DEFAULT_ALBUM_VIDEO_DESCRIPTION,
+        }
+    }
+}
+
+const DEFAULT_VIDEO_DESCRIPTION: &str = "\
+Artist: {{track.artist}}
+Track: {{track.title}}
+Album: {{album.title}}{{#album.year}} ({{album.year}}){{/album.year}}
+Track number: {{track.number}}
+{{#track.bpm}}
+BPM: {{track.bpm}}
+{{/track.bpm}}
+
+{{#album.tags}}
+Tags: {{album.tags}}
+{{/album.tags}}
+{{#album.labels}}
+Released by: {{album.labels}}
+{{/album.labels}}
+{{#track.attribution}}
+
+{{track.attribution}}
+{{/track.attribution}}
+";
+
+// concat! only takes literals, so the parts shared by the album descriptions are macros.
+macro_rules! album_header {
+    () => {
+        "\
+{{album.title}}{{#album.artist}} by {{album.artist}}{{/album.artist}}
+{{#album.release_date}}
+Released: {{album.release_date}}
+{{/album.release_date}}
+{{^album.release_date}}
+{{#album.year}}
+Released: {{album.year}}
+{{/album.year}}
+{{/album.release_date}}
+{{#album.labels}}
+Label: {{album.labels}}
+{{/album.labels}}
+{{#album.tags}}
+Tags: {{album.tags}}
+{{/album.tags}}
+{{#album.description}}
+
+{{album.description}}
+{{/album.description}}
+
+"
+    };
+}
+
+macro_rules! album_footer {
+    () => {
+        "\
+{{#album.attribution}}
+
+{{album.attribution}}
+{{/album.attribution}}
+"
+    };
+}
+
+const DEFAULT_PLAYLIST_DESCRIPTION: &str = concat!(
+    album_header!(),
+    "\
+Tracklist:
+{{#album.tracks}}
+{{number}}. {{artist}} - {{title}}{{#bpm}} ({{bpm}} BPM){{/bpm}}{{#video_url}} {{video_url}}{{/video_url}}
+{{/album.tracks}}
+",
+    album_footer!()
+);
+
+// Same as the playlist but with chapters instead of links to the track videos.
+const DEFAULT_ALBUM_VIDEO_DESCRIPTION: &str = concat!(
+    album_header!(),
+    "\
+{{#album.chapters}}
+{{#start}}{{start}} {{/start}}{{artist}} - {{title}}
+{{/album.chapters}}
+",
+    album_footer!()
+);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text(s) => s.is_empty(),
+            Value::List(l) => l.is_empty(),
+            Value::Map(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Var(String),
+    Section {
+        name: String,
+        inverted: bool,
+        children: Vec<Node>,
+    },
+}
+
+// Name of section and whether it's inverted.
+type Opening = (String, bool);
+
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(src: &str) -> Result<Template, util::Error> {
+        // innermost open section last, the bottom is the template itself
+        let mut stack: Vec<(Option<Opening>, Vec<Node>)> = vec![(None, vec![])];
+
+        for token in tokenize(src)? {
+            let (sigil, name) = match token {
+                Token::Text(s) => {
+                    stack.last_mut().unwrap().1.push(Node::Text(s));
+                    continue;
+                }
+                Token::Tag(sigil, name) => (sigil, name),
+            };
+            match sigil {
+                None => stack.last_mut().unwrap().1.push(Node::Var(name)),
+                Some('!') => (),
+                Some('/') => {
+                    let (open, children) = stack.pop().unwrap();
+                    match open {
+                        Some((n, inverted)) if n == name => {
+                            stack.last_mut().unwrap().1.push(Node::Section {
+                                name,
+                                inverted,
+                                children,
+                            })
+                        }
+                        Some((n, _)) => {
+                            return Err(util::Error::new(&format!(
+                                "Section {{{{{}}}}} closed by {{{{/{}}}}}",
+                                n, name
+                            )))
+                        }
+                        None => {
+                            return Err(util::Error::new(&format!("Unexpected {{{{/{}}}}}", name)))
+                        }
+                    }
+                }
+                Some(c) => stack.push((Some((name, c == '^')), vec![])),
+            }
+        }
+
+        let (open, nodes) = stack.pop().unwrap();
+        match open {
+            None => Ok(Template { nodes }),
+            Some((n, _)) => Err(util::Error::new(&format!("Unclosed section {}", n))),
+        }
+    }
+
+    pub fn render(&self, context: &Value) -> String {
+        let mut res = String::new();
+        render_nodes(&self.nodes, &mut vec![context], &mut res);
+        res
+    }
+}
+
+enum Token {
+    Text(String),
+    Tag(Option<char>, String),
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, util::Error> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while let Some(off) = src[pos..].find("{{") {
+        let start = pos + off;
+        let end = match src[start..].find("}}") {
+            Some(e) => start + e + 2,
+            None => return Err(util::Error::new("Unterminated {{")),
+        };
+        let inner = src[start + 2..end - 2].trim();
+        let (sigil, name) = match inner.chars().next() {
+            Some(c) if "#^/!".contains(c) => (Some(c), inner[1..].trim()),
+            _ => (None, inner),
+        };
+        if name.is_empty() && sigil != Some('!') {
+            return Err(util::Error::new("Empty tag name"));
+        }
+
+        // standalone tags take the whole line with them
+        let mut text_end = start;
+        let mut next = end;
+        if sigil.is_some() {
+            let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = src[end..].find('\n').map_or(src.len(), |i| end + i + 1);
+            if line_start >= pos
+                && src[line_start..start].trim().is_empty()
+                && src[end..line_end].trim().is_empty()
+            {
+                text_end = line_start;
+                next = line_end;
+            }
+        }
+
+        if text_end > pos {
+            tokens.push(Token::Text(src[pos..text_end].to_string()));
+        }
+        tokens.push(Token::Tag(sigil, name.to_string()));
+        pos = next;
+    }
+    if pos < src.len() {
+        tokens.push(Token::Text(src[pos..].to_string()));
+    }
+
+    Ok(tokens)
+}
+
+// Innermost context first, "." is the current item.
+fn lookup<'a>(stack: &[&'a Value], name: &str) -> Option<&'a Value> {
+    if name == "." {
+        return stack.last().cloned();
+    }
+    let mut parts = name.split('.');
+    let first = parts.next()?;
+    let mut value = stack.iter().rev().find_map(|ctx| match ctx {
+        Value::Map(m) => m.get(first),
+        _ => None,
+    })?;
+    for part in parts {
+        value = match value {
+            Value::Map(m) => m.get(part)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn render_nodes<'a>(nodes: &'a [Node], stack: &mut Vec<&'a Value>, res: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(s) => res.push_str(s),
+            Node::Var(name) => match lookup(stack, name) {
+                Some(Value::Text(s)) => res.push_str(s),
+                Some(Value::List(l)) => {
+                    let items: Vec<&str> = l
+                        .iter()
+                        .filter_map(|v| match v {
+                            Value::Text(s) => Some(s.as_str()),
+                            _ => None,
+                        })
+                        .collect();
+                    res.push_str(&items.join(", "));
+                }
+                Some(Value::Map(_)) | None => (),
+            },
+            Node::Section {
+                name,
+                inverted,
+                children,
+            } => {
+                let value = lookup(stack, name).filter(|v| !v.is_empty());
+                match (value, inverted) {
+                    (None, true) => render_nodes(children, stack, res),
+                    (None, false) | (Some(_), true) => (),
+                    (Some(Value::List(items)), false) => {
+                        for item in items {
+                            stack.push(item);
+                            render_nodes(children, stack, res);
+                            stack.pop();
+                        }
+                    }
+                    (Some(v), false) => {
+                        stack.push(v);
+                        render_nodes(children, stack, res);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Skips missing and empty values so that they don't pass as set in sections.
+fn put<T: ToString>(map: &mut BTreeMap<String, Value>, key: &str, value: Option<T>) {
+    if let Some(v) = value {
+        let s = v.to_string();
+        if !s.is_empty() {
+            map.insert(key.to_string(), Value::Text(s));
+        }
+    }
+}
+
+fn track_value(album: &Album, index: usize, track: &Track) -> Value {
+    let mut m = BTreeMap::new();
+    put(&mut m, "number", Some(format!("{:02}", index + 1)));
+    put(&mut m, "artist", Some(&track.artist));
+    put(&mut m, "title", Some(&track.title));
+    put(&mut m, "bpm", track.bpm);
+    put(
+        &mut m,
+        "mp3_file",
+        track.mp3_file.as_ref().map(|p| p.display()),
+    );
+    put(
+        &mut m,
+        "video_file",
+        track.video_file.as_ref().map(|p| p.display()),
+    );
+    put(&mut m, "youtube_id", track.youtube_id.as_ref());
+    put(
+        &mut m,
+        "video_url",
+        track.youtube_id.as_ref().map(youtube::VideoID::as_url),
+    );
+    put(
+        &mut m,
+        "attribution",
+        album
+            .license
+            .as_ref()
+            .map(|l| license::License::parse(l).attribution(&track.title, &track.artist)),
+    );
+    Value::Map(m)
+}
+
+fn album_map(album: &Album) -> BTreeMap<String, Value> {
+    let mut m = BTreeMap::new();
+    put(&mut m, "url", Some(&album.url));
+    put(&mut m, "artist", album.artist.as_ref());
+    put(&mut m, "title", Some(&album.title));
+    put(&mut m, "license", album.license.as_ref());
+    put(
+        &mut m,
+        "license_name",
+        album
+            .license
+            .as_ref()
+            .and_then(|l| license::License::parse(l).name()),
+    );
+    put(
+        &mut m,
+        "attribution",
+        album.license.as_ref().map(|l| {
+            license::License::parse(l).attribution(
+                &album.title,
+                album.artist.as_deref().unwrap_or("Various Artists"),
+            )
+        }),
+    );
+    put(&mut m, "year", album.year);
+    put(&mut m, "release_date", album.release_date);
+    put(&mut m, "labels", Some(album.labels.join(" & ")));
+    put(&mut m, "tags", Some(album.tags.join(", ")));
+    put(&mut m, "description", album.description.as_ref());
+    put(&mut m, "download_url", album.download_url.as_ref());
+    put(&mut m, "youtube_id", album.youtube_id.as_ref());
+    put(
+        &mut m,
+        "playlist_url",
+        album.youtube_id.as_ref().map(youtube::PlaylistID::as_url),
+    );
+    put(
+        &mut m,
+        "playlist_title",
+        Some(youtube::playlist_title(
+            &album.title,
+            &album.artist,
+            &album.year,
+            &album.tags,
+        )),
+    );
+    m.insert(
+        "tracks".to_string(),
+        Value::List(
+            album
+                .tracks
+                .iter()
+                .enumerate()
+                .map(|(i, t)| track_value(album, i, t))
+                .collect(),
+        ),
+    );
+    m
+}
+
+// Context for the templates: album and, for videos, the track at index.
+pub fn context(album: &Album, track: Option<usize>) -> Value {
+    let mut m = BTreeMap::new();
+    m.insert("album".to_string(), Value::Map(album_map(album)));
+    if let Some(i) = track {
+        m.insert("track".to_string(), track_value(album, i, &album.tracks[i]));
+    }
+    Value::Map(m)
+}
+
+// Context for the album video, durations of the tracks in seconds give the chapter list.
+// YouTube ignores chapters unless there are at least 3 of them and each is 10 seconds or
+// longer, the tracks are listed without start then.
+pub fn album_video_context(album: &Album, durations: &[f64]) -> Value {
+    let valid =
+        album.tracks.len().min(durations.len()) >= 3 && durations.iter().all(|d| *d >= 10.0);
+    if !valid {
+        log::warn!("Album video too short for YouTube chapters, listing tracks without start");
+    }
+    let mut chapters = vec![];
+    let mut start = 0.0;
+    for (i, (t, d)) in album.tracks.iter().zip(durations).enumerate() {
+        let mut m = BTreeMap::new();
+        if valid {
+            put(&mut m, "start", Some(timestamp(start)));
+        }
+        put(&mut m, "number", Some(format!("{:02}", i + 1)));
+        put(&mut m, "artist", Some(&t.artist));
+        put(&mut m, "title", Some(&t.title));
+        chapters.push(Value::Map(m));
+        start += d;
+    }
+    let mut album_m = album_map(album);
+    album_m.insert("chapters".to_string(), Value::List(chapters));
+    let mut m = BTreeMap::new();
+    m.insert("album".to_string(), Value::Map(album_m));
+    Value::Map(m)
+}
+
+// YouTube chapter timestamp, 01:02 or 1:02:03.
+fn timestamp(secs: f64) -> String {
+    let secs = secs as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// User template from the directory, the source specific one takes precedence. None if there's
+// neither.
+pub fn load(dir: &Path, source: &str, kind: TemplateKind) -> Result<Option<Template>, util::Error> {
+    for path in &[
+        dir.join(source).join(kind.file_name()),
+        dir.join(kind.file_name()),
+    ] {
+        if path.exists() {
+            let src = std::fs::read_to_string(path)?;
+            return Template::parse(&src)
+                .map(Some)
+                .map_err(|e| util::Error::new(&format!("{}: {}", path.display(), e)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn map(items: Vec<(&str, Value)>) -> Value {
+        Value::Map(items.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    #[test]
+    fn render() {
+        let ctx = map(vec![
+            ("title", text("Forgot")),
+            ("empty", text("")),
+            (
+                "album",
+                map(vec![("year", text("2012")), ("tags", text("Dub, Ambient"))]),
+            ),
+            (
+                "tracks",
+                Value::List(vec![
+                    map(vec![("title", text("A")), ("bpm", text("88"))]),
+                    map(vec![("title", text("B"))]),
+                ]),
+            ),
+            ("names", Value::List(vec![text("x"), text("y")])),
+        ]);
+        let cases = [
+            ("{{title}}{{! ignored }}", "Forgot"),
+            ("{{ album.year }} {{missing}}{{album.missing.x}}", "2012 "),
+            ("{{#album.year}}({{album.year}}){{/album.year}}", "(2012)"),
+            ("{{#empty}}x{{/empty}}{{^empty}}none{{/empty}}", "none"),
+            ("{{^album.year}}x{{/album.year}}", ""),
+            (
+                "{{#tracks}}{{title}}{{#bpm}} {{bpm}}{{/bpm}} ({{album.year}});{{/tracks}}",
+                "A 88 (2012);B (2012);",
+            ),
+            ("{{#album}}{{tags}}{{/album}}", "Dub, Ambient"),
+            ("{{names}}|{{#names}}[{{.}}]{{/names}}", "x, y|[x][y]"),
+            // standalone lines
+            (
+                "Title: {{title}}\n  {{#album.year}}  \nYear: {{album.year}}\n{{/album.year}}\nEnd\n",
+                "Title: Forgot\nYear: 2012\nEnd\n",
+            ),
+            (
+                "{{#empty}}\nx\n{{/empty}}\n{{!c}}\nEnd",
+                "End",
+            ),
+            ("a {{#title}}b{{/title}}\nc", "a b\nc"),
+        ];
+        for (src, expected) in &cases {
+            let t = Template::parse(src).unwrap();
+            assert_eq!(&t.render(&ctx), expected, "{:?}", src);
+        }
+
+        for src in &[
+            "{{#a}}",
+            "{{/a}}",
+            "{{#a}}{{/b}}",
+            "{{#a}}{{#b}}{{/a}}{{/b}}",
+            "{{a",
+            "{{}}",
+        ] {
+            assert!(Template::parse(src).is_err(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn default_templates() {
+        let album = Album {
+            url: "https://ektoplazm.com/free-music/asdfasdf".to_string(),
+            artist: Some("Risingson".to_string()),
+            title: "Forgot".to_string(),
+            license: Some("https://creativecommons.org/licenses/by-nc-sa/4.0/".to_string()),
+            year: Some(2012),
+            labels: vec!["Ektoplazm".to_string(), "Other".to_string()],
+            tags: vec!["Dub".to_string(), "Ambient".to_string()],
+            tracks: vec![Track {
+                artist: "Risingson".to_string(),
+                title: "Digital Being".to_string(),
+                bpm: Some(88),
+                mp3_file: Some(PathBuf::from("01 - Risingson - Digital Being.mp3")),
+                video_file: None,
+                profile: None,
+                youtube_id: None,
+            }],
+            youtube_id: None,
+            release_date: None,
+            description: None,
+            download_url: None,
+            album_video_id: None,
+            notes: vec![],
+        };
+        let ctx = context(&album, Some(0));
+        let render =
+            |kind: TemplateKind| Template::parse(kind.default_source()).unwrap().render(&ctx);
+
+        assert_eq!(
+            render(TemplateKind::VideoTitle),
+            "Risingson - Digital Being"
+        );
+        assert_eq!(
+            render(TemplateKind::VideoDescription),
+            "Artist: Risingson
+Track: Digital Being
+Album: Forgot (2012)
+Track number: 01
+BPM: 88
+
+Tags: Dub, Ambient
+Released by: Ektoplazm & Other
+
+\"Digital Being\" by Risingson is licensed under CC BY-NC-SA 4.0: \
+https://creativecommons.org/licenses/by-nc-sa/4.0/
+"
+        );
+        assert_eq!(
+            render(TemplateKind::PlaylistTitle),
+            "Risingson - Forgot (2012) [Dub,Ambient]"
+        );
+        let album = Album {
+            release_date: Some(chrono::NaiveDate::from_ymd(2012, 3, 4)),
+            description: Some("Second album.".to_string()),
+            tracks: vec![
+                Track {
+                    youtube_id: Some(youtube::VideoID("FAKE0000001".to_string())),
+                    ..album.tracks[0].clone()
+                },
+                Track {
+                    artist: "Risingson & Friend".to_string(),
+                    title: "Robosapiens".to_string(),
+                    bpm: None,
+                    mp3_file: None,
+                    video_file: None,
+                    profile: None,
+                    youtube_id: None,
+                },
+            ],
+            ..album
+        };
+        assert_eq!(
+            Template::parse(TemplateKind::PlaylistDescription.default_source())
+                .unwrap()
+                .render(&context(&album, None)),
+            "Forgot by Risingson
+Released: 2012-03-04
+Label: Ektoplazm & Other
+Tags: Dub, Ambient
+
+Second album.
+
+Tracklist:
+01. Risingson - Digital Being (88 BPM) https://www.youtube.com/watch?v=FAKE0000001
+02. Risingson & Friend - Robosapiens
+
+\"Forgot\" by Risingson is licensed under CC BY-NC-SA 4.0: \
+https://creativecommons.org/licenses/by-nc-sa/4.0/
+"
+        );
+
+        let ctx = album_video_context(&album, &[3599.7, 10.0]);
+        assert!(
+            Template::parse(TemplateKind::AlbumVideoDescription.default_source())
+                .unwrap()
+                .render(&ctx)
+                .contains("\n\nRisingson - Digital Being\nRisingson & Friend - Robosapiens\n\n")
+        );
+
+        let mut album = album;
+        album.tracks.push(album.tracks[0].clone());
+        let ctx = album_video_context(&album, &[3599.7, 10.0, 20.0]);
+        assert_eq!(
+            Template::parse(TemplateKind::AlbumVideoDescription.default_source())
+                .unwrap()
+                .render(&ctx),
+            "Forgot by Risingson
+Released: 2012-03-04
+Label: Ektoplazm & Other
+Tags: Dub, Ambient
+
+Second album.
+
+00:00 Risingson - Digital Being
+59:59 Risingson & Friend - Robosapiens
+1:00:09 Risingson - Digital Being
+
+\"Forgot\" by Risingson is licensed under CC BY-NC-SA 4.0: \
+https://creativecommons.org/licenses/by-nc-sa/4.0/
+"
+        );
+        assert_eq!(timestamp(3725.0), "1:02:05");
+    }
+
+    #[test]
+    fn load_user_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let kind = TemplateKind::VideoTitle;
+        assert_eq!(load(dir.path(), "ektoplazm", kind).unwrap(), None);
+
+        std::fs::write(dir.path().join(kind.file_name()), "{{track.title}}").unwrap();
+        std::fs::create_dir(dir.path().join("ektoplazm")).unwrap();
+        std::fs::write(
+            dir.path().join("ektoplazm").join(kind.file_name()),
+            "{{#track.title}}",
+        )
+        .unwrap();
+        assert_eq!(
+            load(dir.path(), "bandcamp", kind).unwrap(),
+            Some(Template::parse("{{track.title}}").unwrap())
+        );
+        let err = load(dir.path(), "ektoplazm", kind).unwrap_err();
+        assert!(err.to_string().contains("Unclosed section"));
+    }
+}
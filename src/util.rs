@@ -47,6 +47,16 @@ impl Error {
         }
     }
 
+    // Marks the error as temporary, e.g. exceeded quota or network problem.
+    pub fn retriable(mut self) -> Error {
+        self.retry_later = true;
+        self
+    }
+
+    pub fn is_retriable(&self) -> bool {
+        self.retry_later
+    }
+
     fn wrap<T: std::error::Error + 'static>(msg: &str, source: T) -> Error {
         Error {
             msg: msg.to_string(),
@@ -68,7 +78,8 @@ impl From<&str> for Error {
 
 impl From<hyper::Error> for Error {
     fn from(err: hyper::Error) -> Self {
-        Error::wrap("HTTP error", err)
+        // connection problems usually go away
+        Error::wrap("HTTP error", err).retriable()
     }
 }
 
@@ -96,6 +107,12 @@ impl From<id3::Error> for Error {
     }
 }
 
+impl From<claxon::Error> for Error {
+    fn from(err: claxon::Error) -> Self {
+        Error::wrap("Error reading FLAC file", err)
+    }
+}
+
 impl From<youtube3::Error> for Error {
     fn from(err: youtube3::Error) -> Self {
         let mut retry = false;
@@ -130,7 +147,9 @@ impl From<regex::Error> for Error {
     }
 }
 
-pub fn retry<T, F>(retries: u32, t: chrono::Duration, f: F) -> Result<T>
+// Calls f until it succeeds or fails with non-retriable error, at most retries + 1 times.
+// Returns the last result and how many times f was called.
+pub fn retry_counted<T, F>(retries: u32, t: chrono::Duration, f: F) -> (Result<T>, u32)
 where
     F: Fn() -> Result<T>,
 {
@@ -140,7 +159,7 @@ where
             Err(e) if e.retry_later => {
                 log::debug!("Retriable error: {:?}", e);
             }
-            _ => return res,
+            _ => return (res, i + 1),
         }
 
         log::info!(
@@ -151,5 +170,5 @@ where
         std::thread::sleep(t.to_std().expect("valid duration"));
         res = f();
     }
-    res
+    (res, retries + 1)
 }
@@ -1,9 +1,11 @@
 use std::path::{Path, PathBuf};
 use std::process;
 
+use crate::model::Album;
 use crate::util;
 
 use regex::Regex;
+use serde::Deserialize;
 
 pub fn find_cover(dir: &Path) -> Result<PathBuf, util::Error> {
     let entries: Vec<std::fs::DirEntry> = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
@@ -68,27 +70,824 @@ fn temp_video_file(final_file: &Path) -> util::Result<PathBuf> {
     Ok(res)
 }
 
+// How the audio gets into the video.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioCodec {
+    Copy,
+    Flac,
+    Aac(u32), // kbit/s
+    Opus(u32),
+}
+
+impl AudioCodec {
+    pub fn from_name(name: &str, bitrate: Option<u32>) -> Result<AudioCodec, util::Error> {
+        match name {
+            "copy" => Ok(AudioCodec::Copy),
+            "flac" => Ok(AudioCodec::Flac),
+            "aac" => Ok(AudioCodec::Aac(bitrate.unwrap_or(384))),
+            "opus" => Ok(AudioCodec::Opus(bitrate.unwrap_or(256))),
+            _ => Err(util::Error::new(&format!("Unknown audio codec {}", name))),
+        }
+    }
+
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let args = match self {
+            AudioCodec::Copy => vec!["-c:a", "copy"],
+            AudioCodec::Flac => vec!["-c:a", "flac"],
+            AudioCodec::Aac(_) => vec!["-c:a", "aac"],
+            AudioCodec::Opus(_) => vec!["-c:a", "libopus"],
+        };
+        let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+        if let AudioCodec::Aac(b) | AudioCodec::Opus(b) = self {
+            args.push("-b:a".to_string());
+            args.push(format!("{}k", b));
+        }
+        args
+    }
+
+    // Container that can hold the resulting audio. MP3 stays in AVI as it always did.
+    pub fn extension(&self, audio_file: &Path) -> &'static str {
+        match self {
+            AudioCodec::Copy if audio_file.extension() == Some("mp3".as_ref()) => "avi",
+            AudioCodec::Aac(_) => "mp4",
+            _ => "mkv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Container {
+    Mp4,
+    Mkv,
+    Avi,
+}
+
+impl Container {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::Avi => "avi",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Container> {
+        match name {
+            "mp4" => Some(Container::Mp4),
+            "mkv" => Some(Container::Mkv),
+            "avi" => Some(Container::Avi),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Fit,  // just the cover, letterboxed when the profile has a height
+    Blur, // cover on top of a blurred copy filling the whole frame
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "fit" => Some(Layout::Fit),
+            "blur" => Some(Layout::Blur),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Position {
+    pub fn from_name(name: &str) -> Option<Position> {
+        match name {
+            "top-left" => Some(Position::TopLeft),
+            "top" => Some(Position::Top),
+            "top-right" => Some(Position::TopRight),
+            "bottom-left" => Some(Position::BottomLeft),
+            "bottom" => Some(Position::Bottom),
+            "bottom-right" => Some(Position::BottomRight),
+            _ => None,
+        }
+    }
+
+    // drawtext x and y expressions, margin is in pixels
+    fn expressions(self, margin: u32) -> (String, String) {
+        let x = match self {
+            Position::TopLeft | Position::BottomLeft => margin.to_string(),
+            Position::Top | Position::Bottom => "(w-text_w)/2".to_string(),
+            Position::TopRight | Position::BottomRight => format!("w-text_w-{}", margin),
+        };
+        let y = match self {
+            Position::TopLeft | Position::Top | Position::TopRight => margin.to_string(),
+            _ => format!("h-text_h-{}", margin),
+        };
+        (x, y)
+    }
+}
+
+// Track caption drawn over the video.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlay {
+    pub font: Option<String>, // font file if it contains a slash, fontconfig pattern otherwise
+    pub size: u32,
+    pub position: Position,
+}
+
+impl Default for Overlay {
+    fn default() -> Overlay {
+        Overlay {
+            font: None,
+            size: 36,
+            position: Position::BottomLeft,
+        }
+    }
+}
+
+impl Overlay {
+    fn drawtext(&self, text: &str) -> String {
+        let (x, y) = self.position.expressions(self.size);
+        let font = match &self.font {
+            None => String::new(),
+            Some(f) if f.contains('/') => format!("fontfile={}:", filter_escape(f)),
+            Some(f) => format!("font={}:", filter_escape(f)),
+        };
+        format!(
+            "drawtext={}expansion=none:text={}:fontsize={}:fontcolor=white:\
+             box=1:boxcolor=black@0.5:boxborderw={}:x={}:y={}",
+            font,
+            filter_escape(text),
+            self.size,
+            self.size / 3,
+            x,
+            y
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VisualizerKind {
+    Waveform,
+    Spectrum,
+    Progress, // bar along the bottom edge
+}
+
+impl VisualizerKind {
+    pub fn from_name(name: &str) -> Option<VisualizerKind> {
+        match name {
+            "waveform" => Some(VisualizerKind::Waveform),
+            "spectrum" => Some(VisualizerKind::Spectrum),
+            "progress" => Some(VisualizerKind::Progress),
+            _ => None,
+        }
+    }
+}
+
+// Moving picture composited over the cover.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Visualizer {
+    pub kind: VisualizerKind,
+    pub intensity: f32, // opacity, 0.0 - 1.0
+}
+
+impl Visualizer {
+    // Filter graph from [cover] and [1:a] to an unlabeled output. The progress bar needs
+    // the length of the track.
+    fn filter(&self, width: u32, height: u32, fps: u32, duration: Option<f64>) -> String {
+        let vis_height = height / 4 / 2 * 2;
+        let fade = format!("format=rgba,colorchannelmixer=aa={}", self.intensity);
+        match self.kind {
+            VisualizerKind::Waveform => format!(
+                "[1:a]showwaves=s={}x{}:mode=cline:rate={}:colors=white,{}[vis];\
+                 [cover][vis]overlay=0:H-h:shortest=1",
+                width, vis_height, fps, fade
+            ),
+            VisualizerKind::Spectrum => format!(
+                "[1:a]showspectrum=s={}x{}:mode=combined:slide=scroll:color=intensity,\
+                 fps={},{}[vis];[cover][vis]overlay=0:H-h:shortest=1",
+                width, vis_height, fps, fade
+            ),
+            // full width bar sliding in from the left, t is the timestamp in overlay
+            VisualizerKind::Progress => format!(
+                "color=c=white@{i}:s={w}x{h}:r={fps},format=rgba[bar];\
+                 [cover][bar]overlay=x=-w+W*t/{d}:y=H-h:eval=frame",
+                i = self.intensity,
+                w = width,
+                h = height / 80 * 2,
+                fps = fps,
+                d = duration.unwrap_or(1.0).max(1.0)
+            ),
+        }
+    }
+}
+
+// Option values inside -vf are unescaped twice: once when splitting the filter graph and
+// once when parsing the filter options.
+fn filter_escape(value: &str) -> String {
+    let escape = |s: &str, special: &[char]| {
+        let mut res = String::new();
+        for c in s.chars() {
+            if special.contains(&c) {
+                res.push('\\');
+            }
+            res.push(c);
+        }
+        res
+    };
+    let value = escape(value, &['\\', '\'', ':']);
+    escape(&value, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+// Everything about the ffmpeg invocation that can be configured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub container: Option<Container>, // None: whatever fits the audio codec
+    pub video_codec: Option<String>,  // None: ffmpeg default for the container
+    pub frame_rate: u32,
+    pub width: u32,          // cover is never scaled up
+    pub height: Option<u32>, // letterbox into width x height, otherwise keep aspect ratio
+    pub layout: Layout,
+    pub overlay: Option<Overlay>,
+    pub visualizer: Option<Visualizer>, // None: still image
+    pub pixel_format: Option<String>,
+    pub audio_codec: AudioCodec,
+    pub faststart: bool, // moov atom at the start, mp4 only
+}
+
+pub const DEFAULT_PROFILE: &str = "legacy";
+
+// What ektoboat always did: one frame per second, 800px wide, audio copied into AVI.
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            name: DEFAULT_PROFILE.to_string(),
+            container: None,
+            video_codec: None,
+            frame_rate: 1,
+            width: 800,
+            height: None,
+            layout: Layout::Fit,
+            overlay: None,
+            visualizer: None,
+            pixel_format: None,
+            audio_codec: AudioCodec::Copy,
+            faststart: false,
+        }
+    }
+}
+
+// Profile as written in profiles.json, missing values are taken from the default profile.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileSpec {
+    container: Option<String>,
+    video_codec: Option<String>,
+    frame_rate: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    layout: Option<String>,
+    overlay: Option<OverlaySpec>,
+    visualizer: Option<VisualizerSpec>,
+    pixel_format: Option<String>,
+    audio_codec: Option<String>,
+    audio_bitrate: Option<u32>,
+    faststart: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OverlaySpec {
+    font: Option<String>,
+    size: Option<u32>,
+    position: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct VisualizerSpec {
+    kind: Option<String>,
+    intensity: Option<f32>,
+}
+
+impl VisualizerSpec {
+    fn to_visualizer(&self) -> util::Result<Visualizer> {
+        let kind = match &self.kind {
+            None => VisualizerKind::Waveform,
+            Some(k) => VisualizerKind::from_name(k)
+                .ok_or_else(|| util::Error::new(&format!("Unknown visualizer {}", k)))?,
+        };
+        let intensity = self.intensity.unwrap_or(0.8);
+        if !(0.0..=1.0).contains(&intensity) {
+            return Err(util::Error::new(
+                "Visualizer intensity must be between 0 and 1",
+            ));
+        }
+        Ok(Visualizer { kind, intensity })
+    }
+}
+
+impl OverlaySpec {
+    fn to_overlay(&self) -> util::Result<Overlay> {
+        let default = Overlay::default();
+        let position = match &self.position {
+            None => default.position,
+            Some(p) => Position::from_name(p)
+                .ok_or_else(|| util::Error::new(&format!("Unknown overlay position {}", p)))?,
+        };
+        Ok(Overlay {
+            font: self.font.clone(),
+            size: self.size.unwrap_or(default.size),
+            position,
+        })
+    }
+}
+
+impl ProfileSpec {
+    fn to_profile(&self, name: &str) -> util::Result<Profile> {
+        let default = Profile::default();
+        let container = match &self.container {
+            None => None,
+            Some(c) => Some(
+                Container::from_name(c)
+                    .ok_or_else(|| util::Error::new(&format!("Unknown container {}", c)))?,
+            ),
+        };
+        let layout = match &self.layout {
+            None => default.layout,
+            Some(l) => Layout::from_name(l)
+                .ok_or_else(|| util::Error::new(&format!("Unknown layout {}", l)))?,
+        };
+        let overlay = match &self.overlay {
+            None => None,
+            Some(o) => Some(o.to_overlay()?),
+        };
+        let visualizer = match &self.visualizer {
+            None => None,
+            Some(v) => Some(v.to_visualizer()?),
+        };
+        // one frame per second only makes sense for a still image
+        let frame_rate = match (self.frame_rate, &visualizer) {
+            (Some(r), _) => r,
+            (None, Some(_)) => 25,
+            (None, None) => default.frame_rate,
+        };
+        let audio_codec = match &self.audio_codec {
+            None => default.audio_codec,
+            Some(a) => AudioCodec::from_name(a, self.audio_bitrate)?,
+        };
+        Ok(Profile {
+            name: name.to_string(),
+            container,
+            video_codec: self.video_codec.clone(),
+            frame_rate,
+            width: self.width.unwrap_or(default.width),
+            height: self.height,
+            layout,
+            overlay,
+            visualizer,
+            pixel_format: self.pixel_format.clone(),
+            audio_codec,
+            faststart: self.faststart.unwrap_or(false),
+        })
+    }
+}
+
+impl Profile {
+    pub fn builtin() -> Vec<Profile> {
+        vec![
+            Profile::default(),
+            Profile {
+                name: "youtube-mp4".to_string(),
+                container: Some(Container::Mp4),
+                video_codec: Some("libx264".to_string()),
+                frame_rate: 1,
+                width: 1280,
+                height: Some(720),
+                layout: Layout::Fit,
+                overlay: None,
+                visualizer: None,
+                pixel_format: Some("yuv420p".to_string()),
+                audio_codec: AudioCodec::Aac(384),
+                faststart: true,
+            },
+            Profile {
+                name: "youtube-blur".to_string(),
+                container: Some(Container::Mp4),
+                video_codec: Some("libx264".to_string()),
+                frame_rate: 1,
+                width: 1920,
+                height: Some(1080),
+                layout: Layout::Blur,
+                overlay: Some(Overlay::default()),
+                visualizer: None,
+                pixel_format: Some("yuv420p".to_string()),
+                audio_codec: AudioCodec::Aac(384),
+                faststart: true,
+            },
+            Profile {
+                name: "youtube-waveform".to_string(),
+                container: Some(Container::Mp4),
+                video_codec: Some("libx264".to_string()),
+                frame_rate: 25,
+                width: 1280,
+                height: Some(720),
+                layout: Layout::Blur,
+                overlay: Some(Overlay::default()),
+                visualizer: Some(Visualizer {
+                    kind: VisualizerKind::Waveform,
+                    intensity: 0.8,
+                }),
+                pixel_format: Some("yuv420p".to_string()),
+                audio_codec: AudioCodec::Aac(384),
+                faststart: true,
+            },
+            Profile {
+                name: "mkv-flac".to_string(),
+                container: Some(Container::Mkv),
+                video_codec: Some("libx264".to_string()),
+                frame_rate: 1,
+                width: 1280,
+                height: Some(720),
+                layout: Layout::Fit,
+                overlay: None,
+                visualizer: None,
+                pixel_format: Some("yuv420p".to_string()),
+                audio_codec: AudioCodec::Flac,
+                faststart: false,
+            },
+        ]
+    }
+
+    // User profiles from profiles_file take precedence over the built-in ones.
+    pub fn load(profiles_file: &Path, name: &str) -> util::Result<Profile> {
+        if profiles_file.exists() {
+            let json = std::fs::read_to_string(profiles_file)?;
+            let specs: std::collections::HashMap<String, ProfileSpec> =
+                serde_json::from_str(&json)?;
+            if let Some(spec) = specs.get(name) {
+                return spec.to_profile(name);
+            }
+        }
+        Profile::builtin()
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| util::Error::new(&format!("Unknown video profile {}", name)))
+    }
+
+    pub fn extension(&self, audio_file: &Path) -> &'static str {
+        match self.container {
+            Some(c) => c.as_str(),
+            None => self.audio_codec.extension(audio_file),
+        }
+    }
+
+    // AVI with the default encoder keeps the exact aspect ratio of the cover like before
+    // profiles existed.
+    fn legacy_output(&self) -> bool {
+        let avi = match self.container {
+            Some(c) => c == Container::Avi,
+            None => self.audio_codec == AudioCodec::Copy,
+        };
+        avi && self.video_codec.is_none() && self.pixel_format.is_none()
+    }
+
+    // Visualizers and the blurred background need a fixed frame size, 16:9 by default.
+    fn frame_height(&self) -> Option<u32> {
+        match (self.height, self.layout, &self.visualizer) {
+            (Some(h), _, _) => Some(h),
+            (None, Layout::Fit, None) => None,
+            _ => Some(self.width * 9 / 16 / 2 * 2),
+        }
+    }
+
+    fn video_filter(&self, caption: Option<&str>) -> String {
+        let w = self.width;
+        let mut filter = match (self.layout, self.frame_height()) {
+            (Layout::Fit, None) if self.legacy_output() => format!("scale=min({}\\,in_w):-1", w),
+            // libx264 is the default encoder for mp4 and mkv, it needs even dimensions
+            (Layout::Fit, None) => format!("scale=trunc(min({}\\,in_w)/2)*2:-2", w),
+            (Layout::Fit, Some(h)) => format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2",
+                w = w,
+                h = h
+            ),
+            (Layout::Blur, h) => format!(
+                "split[a][b];\
+                 [a]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},boxblur=20:5[bg];\
+                 [b]scale={w}:{h}:force_original_aspect_ratio=decrease[fg];\
+                 [bg][fg]overlay=(W-w)/2:(H-h)/2,setsar=1",
+                w = w,
+                h = h.unwrap_or_default()
+            ),
+        };
+        if let (Some(overlay), Some(text)) = (&self.overlay, caption) {
+            filter.push(',');
+            filter.push_str(&overlay.drawtext(text));
+        }
+        filter
+    }
+
+    // Cover goes through the layout filter into [cover], the caption is drawn last.
+    fn filter_complex(
+        &self,
+        vis: &Visualizer,
+        caption: Option<&str>,
+        duration: Option<f64>,
+    ) -> String {
+        let mut filter = format!(
+            "[0:v]{},fps={}[cover];{}",
+            self.video_filter(None),
+            self.frame_rate,
+            vis.filter(
+                self.width,
+                self.frame_height().unwrap_or_default(),
+                self.frame_rate,
+                duration
+            )
+        );
+        if let (Some(overlay), Some(text)) = (&self.overlay, caption) {
+            filter.push(',');
+            filter.push_str(&overlay.drawtext(text));
+        }
+        filter.push_str("[v]");
+        filter
+    }
+
+    fn needs_duration(&self) -> bool {
+        match &self.visualizer {
+            Some(v) => v.kind == VisualizerKind::Progress,
+            None => false,
+        }
+    }
+
+    fn ffmpeg_args(&self, caption: Option<&str>, duration: Option<f64>) -> Vec<String> {
+        let mut args: Vec<String> = match &self.visualizer {
+            None => vec!["-vf".to_string(), self.video_filter(caption)],
+            Some(vis) => vec![
+                "-filter_complex".to_string(),
+                self.filter_complex(vis, caption, duration),
+                "-map".to_string(),
+                "[v]".to_string(),
+                "-map".to_string(),
+                "1:a".to_string(),
+            ],
+        };
+        args.push("-r".to_string());
+        args.push(self.frame_rate.to_string());
+        if let Some(c) = &self.video_codec {
+            args.push("-c:v".to_string());
+            args.push(c.clone());
+        }
+        if let Some(p) = &self.pixel_format {
+            args.push("-pix_fmt".to_string());
+            args.push(p.clone());
+        }
+        args.extend(self.audio_codec.ffmpeg_args());
+        if self.faststart {
+            args.push("-movflags".to_string());
+            args.push("+faststart".to_string());
+        }
+        args
+    }
+}
+
+// Length of a media file in seconds.
+pub fn probe_duration(file: &Path) -> util::Result<f64> {
+    #[rustfmt::skip]
+    let output = process::Command::new("ffprobe")
+        .arg("-loglevel").arg("error")
+        .arg("-show_entries").arg("format=duration")
+        .arg("-of").arg("default=noprint_wrappers=1:nokey=1")
+        .arg(file)
+        .output()?;
+    if !output.status.success() {
+        log::error!("stderr: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(util::Error::new("ffprobe failed"));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| util::Error::new(&format!("Cannot determine duration of {:?}", file)))
+}
+
+// Text for the overlay, track is an index into album.tracks.
+pub fn caption(album: &Album, track: usize) -> String {
+    let t = &album.tracks[track];
+    format!(
+        "{} - {}\n{} ({:02}/{:02})",
+        t.artist,
+        t.title,
+        album.title,
+        track + 1,
+        album.tracks.len()
+    )
+}
+
 pub fn convert_file(
     audio_file: &Path,
     image_file: &Path,
     out_file: &Path,
+    profile: &Profile,
+    caption: Option<&str>,
 ) -> Result<(), util::Error> {
-    log::info!("Converting {:?}", audio_file);
+    log::info!("Converting {:?} using profile {}", audio_file, profile.name);
+
+    let duration = if profile.needs_duration() {
+        Some(probe_duration(audio_file)?)
+    } else {
+        None
+    };
+
+    write_via_temp(out_file, |temp_file| {
+        let mut cmd = process::Command::new("ffmpeg");
+        #[rustfmt::skip]
+        cmd.arg("-loglevel").arg("error")
+            .arg("-loop").arg("1")
+            .arg("-i").arg(image_file)
+            .arg("-i").arg(audio_file)
+            .args(profile.ffmpeg_args(caption, duration))
+            .arg("-shortest")
+            .arg(temp_file);
+        run_ffmpeg(cmd)
+    })
+}
+
+// Lets write create the video under a temporary name so that a failure never leaves a partial
+// file behind under the final one.
+pub fn write_via_temp<F>(out_file: &Path, write: F) -> util::Result<()>
+where
+    F: FnOnce(&Path) -> util::Result<()>,
+{
+    let temp_file = temp_video_file(out_file)?;
+    finish(write(&temp_file), &temp_file, out_file)
+}
+
+// Moves the finished video in place, removes the partial one on error.
+fn finish(res: util::Result<()>, temp_file: &Path, out_file: &Path) -> util::Result<()> {
+    let res = res.and_then(|()| Ok(std::fs::rename(temp_file, out_file)?));
+    if res.is_err() && temp_file.exists() {
+        if let Err(e) = std::fs::remove_file(temp_file) {
+            log::warn!("Failed to remove {:?}: {}", temp_file, e);
+        }
+    }
+    res
+}
+
+// Partial videos in the directory, e.g. after being killed in the middle of conversion.
+pub fn remove_temp_files(dir: &Path) -> util::Result<()> {
+    for e in std::fs::read_dir(dir)? {
+        let path = e?.path();
+        if path
+            .file_name()
+            .is_some_and(|f| f.to_string_lossy().starts_with("TEMP "))
+        {
+            log::info!("Removing {:?}", path);
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+// Pictures in the full album video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlbumCovers {
+    Single,   // album cover all the time
+    PerTrack, // art embedded in each track, album cover for tracks without one
+}
+
+impl AlbumCovers {
+    pub fn from_name(name: &str) -> Option<AlbumCovers> {
+        match name {
+            "single" => Some(AlbumCovers::Single),
+            "per-track" => Some(AlbumCovers::PerTrack),
+            _ => None,
+        }
+    }
+}
+
+pub fn album_video_basename(extension: &str) -> PathBuf {
+    PathBuf::from(format!("00 - Full album.{}", extension))
+}
+
+// Concatenates the tracks into one video, returns their durations in seconds.
+pub fn convert_album(
+    audio_files: &[PathBuf],
+    cover: &Path,
+    covers: AlbumCovers,
+    out_file: &Path,
+    profile: &Profile,
+) -> util::Result<Vec<f64>> {
+    log::info!(
+        "Converting {} tracks into {:?} using profile {}",
+        audio_files.len(),
+        out_file,
+        profile.name
+    );
 
     let temp_file = temp_video_file(out_file)?;
+    let tmpdir = tempfile::tempdir()?;
+    // paths in the lists are relative to the list
+    let audio_files = audio_files
+        .iter()
+        .map(|f| f.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    let durations = audio_files
+        .iter()
+        .map(|f| probe_duration(f))
+        .collect::<util::Result<Vec<_>>>()?;
 
+    let audio_list = tmpdir.path().join("audio.txt");
+    let entries: Vec<_> = audio_files.iter().map(|f| (f.clone(), None)).collect();
+    std::fs::write(&audio_list, concat_list(&entries))?;
+
+    let mut cmd = process::Command::new("ffmpeg");
+    cmd.arg("-loglevel").arg("error");
+    match covers {
+        AlbumCovers::Single => {
+            cmd.arg("-loop").arg("1").arg("-i").arg(cover);
+        }
+        AlbumCovers::PerTrack => {
+            let mut entries = vec![];
+            for (i, (f, d)) in audio_files.iter().zip(&durations).enumerate() {
+                let image = tmpdir.path().join(format!("cover {:02}.png", i + 1));
+                let image = match extract_cover(f, &image) {
+                    Ok(()) => image,
+                    Err(e) => {
+                        log::debug!("No cover in {:?}, using the album one: {}", f, e);
+                        cover.canonicalize()?
+                    }
+                };
+                entries.push((image, Some(*d)));
+            }
+            // otherwise the duration of the last one is ignored
+            if let Some((image, _)) = entries.last() {
+                entries.push((image.clone(), None));
+            }
+            let image_list = tmpdir.path().join("images.txt");
+            std::fs::write(&image_list, concat_list(&entries))?;
+            #[rustfmt::skip]
+            cmd.arg("-f").arg("concat")
+                .arg("-safe").arg("0")
+                .arg("-i").arg(&image_list);
+        }
+    }
     #[rustfmt::skip]
-    let output = process::Command::new("ffmpeg")
-        .arg("-loglevel").arg("error")
-        .arg("-loop").arg("1")
-        .arg("-i").arg(image_file)
-        .arg("-i").arg(audio_file)
-        .arg("-vf").arg("scale=min(800\\,in_w):-1")
-        .arg("-r").arg("1")
-        .arg("-acodec").arg("copy")
+    cmd.arg("-f").arg("concat")
+        .arg("-safe").arg("0")
+        .arg("-i").arg(&audio_list)
+        .args(profile.ffmpeg_args(None, Some(durations.iter().sum())))
         .arg("-shortest")
-        .arg(&temp_file)
-        .output()?;
+        .arg(&temp_file);
+    finish(run_ffmpeg(cmd), &temp_file, out_file)?;
+
+    Ok(durations)
+}
+
+// Input for the concat demuxer, still images need a duration.
+fn concat_list(entries: &[(PathBuf, Option<f64>)]) -> String {
+    let mut res = String::from("ffconcat version 1.0\n");
+    for (file, duration) in entries {
+        let quoted = file.to_string_lossy().replace('\'', "'\\''");
+        res.push_str(&format!("file '{}'\n", quoted));
+        if let Some(d) = duration {
+            res.push_str(&format!("duration {}\n", d));
+        }
+    }
+    res
+}
+
+// Picture embedded in the audio file.
+fn extract_cover(audio_file: &Path, out_file: &Path) -> util::Result<()> {
+    let mut cmd = process::Command::new("ffmpeg");
+    #[rustfmt::skip]
+    cmd.arg("-loglevel").arg("error")
+        .arg("-i").arg(audio_file)
+        .arg("-an")
+        .arg("-frames:v").arg("1")
+        .arg("-y")
+        .arg(out_file);
+    // failure is expected for files without a picture, not worth an error message
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(util::Error::new(
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+    Ok(())
+}
+
+fn run_ffmpeg(mut cmd: process::Command) -> util::Result<()> {
+    let output = cmd.output()?;
 
     if !output.status.success() {
         log::error!("ffmpeg failed");
@@ -96,9 +895,6 @@ pub fn convert_file(
         log::error!("stdout: {}", String::from_utf8_lossy(&output.stdout));
         return Err(util::Error::new("ffmpeg failed"));
     }
-
-    std::fs::rename(temp_file, out_file)?;
-
     Ok(())
 }
 
@@ -208,6 +1004,312 @@ mod tests {
         }
     }
 
+    #[test]
+    fn audio_codec() {
+        let mp3 = PathBuf::from("01.mp3");
+        let flac = PathBuf::from("01.flac");
+        let copy = AudioCodec::from_name("copy", Some(128)).unwrap();
+        assert_eq!(copy.extension(&mp3), "avi");
+        assert_eq!(copy.extension(&flac), "mkv");
+        assert_eq!(copy.ffmpeg_args(), vec!["-c:a", "copy"]);
+
+        let aac = AudioCodec::from_name("aac", None).unwrap();
+        assert_eq!(aac, AudioCodec::Aac(384));
+        assert_eq!(aac.extension(&flac), "mp4");
+        assert_eq!(aac.ffmpeg_args(), vec!["-c:a", "aac", "-b:a", "384k"]);
+
+        let opus = AudioCodec::from_name("opus", Some(160)).unwrap();
+        assert_eq!(opus.extension(&mp3), "mkv");
+        assert_eq!(opus.ffmpeg_args(), vec!["-c:a", "libopus", "-b:a", "160k"]);
+
+        assert!(AudioCodec::from_name("vorbis", None).is_err());
+    }
+
+    #[test]
+    fn profile() {
+        let mp3 = PathBuf::from("01.mp3");
+        let legacy = Profile::default();
+        assert_eq!(legacy.extension(&mp3), "avi");
+        assert_eq!(
+            legacy.ffmpeg_args(None, None),
+            vec!["-vf", "scale=min(800\\,in_w):-1", "-r", "1", "-c:a", "copy"]
+        );
+        let x264 = Profile {
+            video_codec: Some("libx264".to_string()),
+            ..Profile::default()
+        };
+        assert_eq!(
+            x264.video_filter(None),
+            "scale=trunc(min(800\\,in_w)/2)*2:-2"
+        );
+        let mkv = Profile {
+            container: Some(Container::Mkv),
+            ..Profile::default()
+        };
+        assert_eq!(
+            mkv.video_filter(None),
+            "scale=trunc(min(800\\,in_w)/2)*2:-2"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("profiles.json");
+        let mp4 = Profile::load(&file, "youtube-mp4").unwrap();
+        assert_eq!(mp4.extension(&mp3), "mp4");
+        assert_eq!(
+            mp4.ffmpeg_args(None, None),
+            vec![
+                "-vf",
+                "scale=1280:720:force_original_aspect_ratio=decrease,pad=1280:720:(ow-iw)/2:(oh-ih)/2",
+                "-r",
+                "1",
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "384k",
+                "-movflags",
+                "+faststart",
+            ]
+        );
+        assert!(Profile::load(&file, "nope").is_err());
+
+        std::fs::write(
+            &file,
+            r#"{
+                "youtube-mp4": {"container": "mp4", "audio_codec": "aac", "audio_bitrate": 192},
+                "tiny": {"width": 320, "frame_rate": 2, "audio_codec": "opus"}
+            }"#,
+        )
+        .unwrap();
+        let mp4 = Profile::load(&file, "youtube-mp4").unwrap();
+        assert_eq!(mp4.audio_codec, AudioCodec::Aac(192));
+        assert_eq!(mp4.video_codec, None);
+        let tiny = Profile::load(&file, "tiny").unwrap();
+        assert_eq!(tiny.extension(&mp3), "mkv");
+        assert_eq!(
+            tiny.ffmpeg_args(None, None),
+            vec![
+                "-vf",
+                "scale=trunc(min(320\\,in_w)/2)*2:-2",
+                "-r",
+                "2",
+                "-c:a",
+                "libopus",
+                "-b:a",
+                "256k"
+            ]
+        );
+        assert!(Profile::load(&file, "mkv-flac").is_ok());
+
+        std::fs::write(&file, r#"{"bad": {"container": "ogg"}}"#).unwrap();
+        assert!(Profile::load(&file, "bad").is_err());
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(filter_escape("a:b"), r"a\\:b");
+        assert_eq!(filter_escape("It's, [ok]"), r"It\\\'s\, \[ok\]");
+
+        let dir = tempfile::tempdir().unwrap();
+        let blur = Profile::load(&dir.path().join("profiles.json"), "youtube-blur").unwrap();
+        assert_eq!(
+            blur.video_filter(None),
+            "split[a][b];\
+             [a]scale=1920:1080:force_original_aspect_ratio=increase,crop=1920:1080,boxblur=20:5[bg];\
+             [b]scale=1920:1080:force_original_aspect_ratio=decrease[fg];\
+             [bg][fg]overlay=(W-w)/2:(H-h)/2,setsar=1"
+        );
+        assert!(blur
+            .video_filter(Some("Globular - Magnitudes: Of Order"))
+            .ends_with(
+                ",setsar=1,drawtext=expansion=none:text=Globular - Magnitudes\\\\: Of Order:\
+                 fontsize=36:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=12:\
+                 x=36:y=h-text_h-36"
+            ));
+
+        let file = dir.path().join("profiles.json");
+        std::fs::write(
+            &file,
+            r#"{"blur": {"width": 1280, "layout": "blur",
+                         "overlay": {"font": "/usr/share/fonts/x.ttf", "size": 24, "position": "top"}}}"#,
+        )
+        .unwrap();
+        let p = Profile::load(&file, "blur").unwrap();
+        assert!(p.video_filter(None).contains("crop=1280:720"));
+        assert!(p.video_filter(Some("x")).ends_with(
+            "drawtext=fontfile=/usr/share/fonts/x.ttf:expansion=none:text=x:fontsize=24:\
+             fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=8:x=(w-text_w)/2:y=24"
+        ));
+        // no caption without an overlay
+        assert_eq!(
+            p.video_filter(None),
+            Profile {
+                overlay: None,
+                ..p.clone()
+            }
+            .video_filter(Some("x"))
+        );
+
+        std::fs::write(&file, r#"{"bad": {"overlay": {"position": "middle"}}}"#).unwrap();
+        assert!(Profile::load(&file, "bad").is_err());
+    }
+
+    #[test]
+    fn visualizer() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("profiles.json");
+        std::fs::write(
+            &file,
+            r#"{
+                "waves": {"visualizer": {}},
+                "bar": {"width": 1920, "height": 1080, "frame_rate": 10, "overlay": {},
+                        "visualizer": {"kind": "progress", "intensity": 0.5}},
+                "loud": {"visualizer": {"intensity": 2.0}}
+            }"#,
+        )
+        .unwrap();
+
+        let waves = Profile::load(&file, "waves").unwrap();
+        assert_eq!(waves.frame_rate, 25);
+        assert!(!waves.needs_duration());
+        assert_eq!(
+            waves.ffmpeg_args(None, None)[..6].to_vec(),
+            vec![
+                "-filter_complex",
+                "[0:v]scale=800:450:force_original_aspect_ratio=decrease,\
+                 pad=800:450:(ow-iw)/2:(oh-ih)/2,fps=25[cover];\
+                 [1:a]showwaves=s=800x112:mode=cline:rate=25:colors=white,\
+                 format=rgba,colorchannelmixer=aa=0.8[vis];\
+                 [cover][vis]overlay=0:H-h:shortest=1[v]",
+                "-map",
+                "[v]",
+                "-map",
+                "1:a",
+            ]
+        );
+
+        let bar = Profile::load(&file, "bar").unwrap();
+        assert!(bar.needs_duration());
+        let args = bar.ffmpeg_args(Some("x"), Some(301.5));
+        assert!(args[1].contains(
+            "fps=10[cover];color=c=white@0.5:s=1920x26:r=10,format=rgba[bar];\
+             [cover][bar]overlay=x=-w+W*t/301.5:y=H-h:eval=frame,drawtext="
+        ));
+        assert!(args[1].ends_with("[v]"));
+
+        assert!(Profile::load(&file, "loud").is_err());
+
+        // still image stays the default
+        assert_eq!(Profile::default().visualizer, None);
+        assert_eq!(Profile::default().ffmpeg_args(None, None)[0], "-vf");
+    }
+
+    // Needs ffmpeg and ffprobe, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn progress_bar_ffmpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let lavfi = |input: &str, out: &Path| {
+            let mut cmd = process::Command::new("ffmpeg");
+            #[rustfmt::skip]
+            cmd.arg("-loglevel").arg("error")
+                .arg("-f").arg("lavfi")
+                .arg("-i").arg(input)
+                .arg("-frames:v").arg("1")
+                .arg(out);
+            run_ffmpeg(cmd).unwrap();
+        };
+        let cover = dir.path().join("cover.png");
+        lavfi("color=c=red:s=64x64", &cover);
+        let audio = dir.path().join("01.wav");
+        let mut cmd = process::Command::new("ffmpeg");
+        #[rustfmt::skip]
+        cmd.arg("-loglevel").arg("error")
+            .arg("-f").arg("lavfi")
+            .arg("-i").arg("sine=duration=2")
+            .arg(&audio);
+        run_ffmpeg(cmd).unwrap();
+
+        let profile = Profile {
+            width: 160,
+            height: Some(90),
+            frame_rate: 5,
+            visualizer: Some(Visualizer {
+                kind: VisualizerKind::Progress,
+                intensity: 1.0,
+            }),
+            ..Profile::default()
+        };
+        let out = dir.path().join("01.mkv");
+        convert_file(&audio, &cover, &out, &profile, None).unwrap();
+        assert!(probe_duration(&out).unwrap() > 1.5);
+
+        // the bar is off screen at the start and covers the bottom edge at the end
+        let bottom_row = |at: &str| {
+            let frame = dir.path().join(format!("frame {}.png", at));
+            let mut cmd = process::Command::new("ffmpeg");
+            #[rustfmt::skip]
+            cmd.arg("-loglevel").arg("error")
+                .arg("-ss").arg(at)
+                .arg("-i").arg(&out)
+                .arg("-frames:v").arg("1")
+                .arg("-vf").arg("crop=iw:1:0:ih-1,scale=1:1")
+                .arg("-f").arg("rawvideo")
+                .arg("-pix_fmt").arg("gray")
+                .arg(&frame);
+            run_ffmpeg(cmd).unwrap();
+            std::fs::read(&frame).unwrap()[0]
+        };
+        assert!(bottom_row("1.8") > bottom_row("0") + 50);
+    }
+
+    #[test]
+    fn album_concat_list() {
+        assert_eq!(
+            concat_list(&[
+                (PathBuf::from("/v/Bob's album/01.mp3"), None),
+                (PathBuf::from("/v/cover.jpg"), Some(61.25)),
+            ]),
+            "ffconcat version 1.0\n\
+             file '/v/Bob'\\''s album/01.mp3'\n\
+             file '/v/cover.jpg'\n\
+             duration 61.25\n"
+        );
+        assert_eq!(
+            AlbumCovers::from_name("per-track"),
+            Some(AlbumCovers::PerTrack)
+        );
+        assert_eq!(
+            album_video_basename("mp4"),
+            PathBuf::from("00 - Full album.mp4")
+        );
+    }
+
+    #[test]
+    fn finish_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_file = dir.path().join("01.avi");
+        let temp_file = temp_video_file(&out_file).unwrap();
+
+        std::fs::write(&temp_file, b"").unwrap();
+        assert!(finish(
+            Err(util::Error::new("ffmpeg failed")),
+            &temp_file,
+            &out_file
+        )
+        .is_err());
+        assert!(!temp_file.exists());
+        assert!(!out_file.exists());
+
+        std::fs::write(&temp_file, b"").unwrap();
+        finish(Ok(()), &temp_file, &out_file).unwrap();
+        assert!(!temp_file.exists());
+        assert!(out_file.exists());
+    }
+
     #[test]
     fn temp_video() {
         assert_eq!(
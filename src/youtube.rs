@@ -9,6 +9,7 @@ use google_youtube3 as youtube3;
 use hyper;
 use hyper_rustls;
 use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
 use yup_oauth2 as oauth2;
 use yup_oauth2::{ApplicationSecret, Authenticator, AuthenticatorDelegate, DiskTokenStorage};
 //use yup_hyper_mock as hyper_mock;
@@ -44,7 +45,7 @@ impl PlaylistID {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Video {
     pub title: String,
     pub description: String,
@@ -52,7 +53,7 @@ pub struct Video {
     pub filename: PathBuf,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Playlist {
     pub title: String,
     pub description: String,
@@ -60,6 +61,163 @@ pub struct Playlist {
     pub videos: Vec<VideoID>,
 }
 
+// The API rejects metadata over these limits, which we'd only find out after uploading the video.
+const MAX_TITLE_CHARS: usize = 100;
+const MAX_PLAYLIST_TITLE_CHARS: usize = 150;
+const MAX_DESCRIPTION_BYTES: usize = 5000;
+const MAX_TAGS_CHARS: usize = 500;
+
+impl Video {
+    // Fits the metadata into the limits, returns notes on what had to be changed.
+    pub fn sanitize(&mut self) -> Vec<String> {
+        let mut notes = Vec::new();
+        sanitize_text(
+            &mut self.title,
+            "title",
+            MAX_TITLE_CHARS,
+            char_len,
+            &mut notes,
+        );
+        sanitize_text(
+            &mut self.description,
+            "description",
+            MAX_DESCRIPTION_BYTES,
+            str::len,
+            &mut notes,
+        );
+        sanitize_tags(&mut self.tags, &mut notes);
+        notes
+    }
+}
+
+impl Playlist {
+    pub fn sanitize(&mut self) -> Vec<String> {
+        let mut notes = Vec::new();
+        sanitize_text(
+            &mut self.title,
+            "title",
+            MAX_PLAYLIST_TITLE_CHARS,
+            char_len,
+            &mut notes,
+        );
+        sanitize_text(
+            &mut self.description,
+            "description",
+            MAX_DESCRIPTION_BYTES,
+            str::len,
+            &mut notes,
+        );
+        sanitize_tags(&mut self.tags, &mut notes);
+        notes
+    }
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+// Angle brackets are not allowed anywhere in titles, descriptions and tags.
+fn replace_angle_brackets(s: &str) -> String {
+    s.replace('<', "\u{2039}").replace('>', "\u{203a}")
+}
+
+fn sanitize_text(
+    text: &mut String,
+    field: &str,
+    max: usize,
+    len: fn(&str) -> usize,
+    notes: &mut Vec<String>,
+) {
+    if text.contains(&['<', '>'][..]) {
+        *text = replace_angle_brackets(text);
+        notes.push(format!("{}: replaced angle brackets", field));
+    }
+    if len(text) > max {
+        *text = truncate(text, max, len);
+        notes.push(format!("{}: truncated to {}", field, max));
+    }
+}
+
+// Shortens the string on grapheme boundary so that it ends with ellipsis and its length is at most
+// max.
+pub fn truncate(s: &str, max: usize, len: fn(&str) -> usize) -> String {
+    const ELLIPSIS: &str = "\u{2026}";
+    if len(s) <= max {
+        return s.to_string();
+    }
+
+    let budget = max.saturating_sub(len(ELLIPSIS));
+    let mut res = String::new();
+    for g in s.graphemes(true) {
+        if len(&res) + len(g) > budget {
+            break;
+        }
+        res.push_str(g);
+    }
+    let mut res = res.trim_end().to_string();
+    if len(&res) + len(ELLIPSIS) <= max {
+        res.push_str(ELLIPSIS);
+    }
+    res
+}
+
+// Tags with spaces are quoted when YouTube counts them, and they're separated by commas.
+fn tag_cost(tag: &str) -> usize {
+    char_len(tag) + if tag.contains(' ') { 2 } else { 0 }
+}
+
+// Drops duplicate tags and the ones that don't fit into the total budget.
+fn sanitize_tags(tags: &mut Vec<String>, notes: &mut Vec<String>) {
+    let mut kept: Vec<String> = Vec::new();
+    let mut dropped = Vec::new();
+    let mut duplicates = 0;
+    let mut total = 0;
+
+    for tag in tags.iter() {
+        let clean = replace_angle_brackets(tag.trim());
+        if clean.is_empty()
+            || kept
+                .iter()
+                .any(|k| k.to_lowercase() == clean.to_lowercase())
+        {
+            duplicates += 1;
+            continue;
+        }
+        let cost = tag_cost(&clean) + if kept.is_empty() { 0 } else { 1 };
+        if total + cost > MAX_TAGS_CHARS {
+            dropped.push(clean);
+            continue;
+        }
+        total += cost;
+        kept.push(clean);
+    }
+
+    if tags.iter().any(|t| t.contains(&['<', '>'][..])) {
+        notes.push("tags: replaced angle brackets".to_string());
+    }
+    if duplicates > 0 {
+        notes.push(format!("tags: removed {} empty or duplicate", duplicates));
+    }
+    if !dropped.is_empty() {
+        notes.push(format!(
+            "tags: dropped {} to fit into {} characters",
+            dropped.join(", "),
+            MAX_TAGS_CHARS
+        ));
+    }
+    *tags = kept;
+}
+
+pub trait Uploader {
+    fn upload_video(&self, video: Video) -> Result<VideoID, util::Error>;
+    fn create_playlist(&self, playlist: Playlist) -> Result<PlaylistID, util::Error>;
+    fn add_video_to_playlist(
+        &self,
+        playlist_id: &PlaylistID,
+        video_id: &VideoID,
+    ) -> Result<(), util::Error>;
+}
+
 // api quota increase request form: https://support.google.com/youtube/contact/yt_api_form?hl=en
 pub struct YT {
     hub: google_youtube3::YouTube<
@@ -103,8 +261,10 @@ impl YT {
 
         Ok(YT { hub: hub })
     }
+}
 
-    pub fn upload_video(&self, video: Video) -> Result<VideoID, util::Error> {
+impl Uploader for YT {
+    fn upload_video(&self, video: Video) -> Result<VideoID, util::Error> {
         log::info!("Uploading {}", video.title);
         let mut v = youtube3::Video::default();
         v.snippet = Some(youtube3::VideoSnippet {
@@ -142,7 +302,7 @@ impl YT {
         Ok(VideoID(video_id))
     }
 
-    pub fn create_playlist(&self, playlist: Playlist) -> Result<PlaylistID, util::Error> {
+    fn create_playlist(&self, playlist: Playlist) -> Result<PlaylistID, util::Error> {
         log::info!("Creating playlist {}", playlist.title);
         let mut p = youtube3::Playlist::default();
 
@@ -176,7 +336,7 @@ impl YT {
         Ok(playlist_id)
     }
 
-    pub fn add_video_to_playlist(
+    fn add_video_to_playlist(
         &self,
         playlist_id: &PlaylistID,
         video_id: &VideoID,
@@ -203,6 +363,84 @@ impl YT {
     }
 }
 
+// Offline stand-in for YouTube, stores what would be uploaded as JSON files in a directory.
+pub struct FakeYT {
+    dir: PathBuf,
+}
+
+impl FakeYT {
+    pub fn new(dir: &Path) -> FakeYT {
+        util::mkdir_if_not_exists(dir);
+        FakeYT {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn next_id(&self, prefix: &str) -> Result<u32, util::Error> {
+        let mut n = 1;
+        for e in fs::read_dir(&self.dir)? {
+            if e?.file_name().to_string_lossy().starts_with(prefix) {
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    fn path(&self, prefix: &str, id: &str) -> PathBuf {
+        let mut p = self.dir.clone();
+        p.push(format!("{}-{}.json", prefix, id));
+        p
+    }
+}
+
+impl Uploader for FakeYT {
+    fn upload_video(&self, video: Video) -> Result<VideoID, util::Error> {
+        log::info!("Fake uploading {}", video.title);
+        // fail the same way as the real upload would
+        fs::File::open(&video.filename)?;
+
+        let video_id = VideoID(format!("FAKE{:07}", self.next_id("video")?));
+        fs::write(
+            self.path("video", &video_id.0),
+            serde_json::to_string_pretty(&video)?,
+        )?;
+        Ok(video_id)
+    }
+
+    fn create_playlist(&self, playlist: Playlist) -> Result<PlaylistID, util::Error> {
+        log::info!("Fake creating playlist {}", playlist.title);
+        let playlist_id = PlaylistID(format!("PLFAKE{:06}", self.next_id("playlist")?));
+        let mut stored = playlist.clone();
+        stored.videos = vec![];
+        fs::write(
+            self.path("playlist", &playlist_id.0),
+            serde_json::to_string_pretty(&stored)?,
+        )?;
+
+        for video_id in &playlist.videos {
+            self.add_video_to_playlist(&playlist_id, video_id)?
+        }
+
+        Ok(playlist_id)
+    }
+
+    fn add_video_to_playlist(
+        &self,
+        playlist_id: &PlaylistID,
+        video_id: &VideoID,
+    ) -> Result<(), util::Error> {
+        log::debug!("Fake adding {} to playlist {}", video_id, playlist_id);
+        let path = self.path("playlist", &playlist_id.0);
+        let mut playlist: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        playlist["videos"]
+            .as_array_mut()
+            .ok_or("Malformed fake playlist")?
+            .push(serde_json::to_value(video_id)?);
+        fs::write(path, serde_json::to_string_pretty(&playlist)?)?;
+        Ok(())
+    }
+}
+
 struct EktoAuthenticatorDelegate;
 
 impl AuthenticatorDelegate for EktoAuthenticatorDelegate {
@@ -281,6 +519,85 @@ pub fn playlist_title(
         }
     }
 
-    // may panic, to do the right thing possibly requires unicode-segmentation library
-    title.chars().take(MAX_LEN).collect()
+    truncate(title, MAX_LEN, str::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_graphemes() {
+        assert_eq!(truncate("short", 10, char_len), "short");
+        assert_eq!(truncate("abcdefghij", 5, char_len), "abcd\u{2026}");
+        // trailing whitespace goes away before the ellipsis
+        assert_eq!(truncate("ab  cdefgh", 5, char_len), "ab\u{2026}");
+        // e + combining acute is one grapheme of 3 bytes, the ellipsis is 3 bytes too
+        let s = "ae\u{301}e\u{301}e";
+        assert_eq!(truncate(s, 8, str::len), s);
+        assert_eq!(truncate(s, 7, str::len), "ae\u{301}\u{2026}");
+        assert_eq!(truncate(s, 6, str::len), "a\u{2026}");
+        assert_eq!(
+            truncate("\u{1f1e8}\u{1f1ff}xyz", 3, char_len),
+            "\u{1f1e8}\u{1f1ff}\u{2026}"
+        );
+        assert_eq!(truncate("abc", 0, char_len), "");
+    }
+
+    #[test]
+    fn sanitize_video() {
+        let mut video = Video {
+            title: "Artist - ".to_string() + &"x".repeat(100),
+            description: "<b>bold</b> ".to_string() + &"\u{e9}".repeat(3000),
+            tags: vec![
+                "Psy Dub".to_string(),
+                "psy dub".to_string(),
+                " ".to_string(),
+                "<3".to_string(),
+                "y".repeat(490),
+                "Dub".to_string(),
+            ],
+            filename: PathBuf::from("video.mkv"),
+        };
+        let notes = video.sanitize();
+
+        assert_eq!(video.title.chars().count(), 100);
+        assert!(video.title.ends_with("x\u{2026}"));
+        assert!(video.description.starts_with("\u{2039}b\u{203a}bold"));
+        assert!(video.description.len() <= 5000);
+        assert_eq!(
+            video.tags,
+            vec![
+                "Psy Dub".to_string(),
+                "\u{2039}3".to_string(),
+                "Dub".to_string()
+            ]
+        );
+        assert_eq!(
+            notes,
+            vec![
+                "title: truncated to 100",
+                "description: replaced angle brackets",
+                "description: truncated to 5000",
+                "tags: replaced angle brackets",
+                "tags: removed 2 empty or duplicate",
+                &format!(
+                    "tags: dropped {} to fit into 500 characters",
+                    "y".repeat(490)
+                ),
+            ]
+        );
+
+        // already fine
+        let mut notes = video.sanitize();
+        assert_eq!(notes, Vec::<String>::new());
+        let mut playlist = Playlist {
+            title: "x".repeat(120),
+            description: String::new(),
+            tags: vec![],
+            videos: vec![],
+        };
+        notes.extend(playlist.sanitize());
+        assert_eq!(notes, Vec::<String>::new());
+    }
 }
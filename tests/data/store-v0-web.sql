@@ -0,0 +1,46 @@
+-- unversioned state.sqlite3 that already has the album web metadata columns
+CREATE TABLE album (
+                id         INTEGER PRIMARY KEY,
+                url        TEXT UNIQUE NOT NULL,
+                artist     TEXT,
+                title      TEXT NOT NULL,
+                license    TEXT,
+                year       INTEGER,
+                labels     TEXT NOT NULL,
+                tags       TEXT NOT NULL,
+                youtube_id TEXT,
+                release_date TEXT,
+                description  TEXT,
+                download_url TEXT
+             );
+CREATE TABLE track (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                album_id   INTEGER NOT NULL REFERENCES album(id),
+                artist     TEXT NOT NULL,
+                title      TEXT NOT NULL,
+                bpm        INTEGER,
+                mp3_file   TEXT,
+                video_file TEXT,
+                youtube_id TEXT
+             );
+CREATE TABLE queue (
+                id          INTEGER PRIMARY KEY,
+                action      TEXT NOT NULL,
+                url         TEXT NOT NULL,
+                result      TEXT,
+                result_date TEXT,
+                UNIQUE      (url, action)
+            );
+CREATE TABLE blacklist (
+                id      INTEGER PRIMARY KEY,
+                type    TEXT NOT NULL CHECK(type IN ('artist', 'label')),
+                pattern TEXT NOT NULL,
+                UNIQUE  (type, pattern)
+            );
+INSERT INTO album VALUES(1,'https://ektoplazm.com/free-music/globular-entangled-everything','Globular','Entangled Everything','https://creativecommons.org/licenses/by-nc-sa/4.0/',2018,'[]','["Downtempo","Psy Dub"]','PL0123','2018-12-29','An eclectic ride through psychedelic downtempo and dub.','https://ektoplazm.com/files/Globular%20-%20Entangled%20Everything%20-%202018%20-%20MP3.zip');
+INSERT INTO track VALUES(1,1,'Globular','Popping Out',NULL,'01 - Globular - Popping Out.mp3','01 - Globular - Popping Out.avi','aaaaaaaaaaa');
+INSERT INTO track VALUES(2,1,'Globular','For The Time Being',65,'02 - Globular - For The Time Being.mp3',NULL,NULL);
+INSERT INTO queue VALUES(1,'url','https://ektoplazm.com/free-music/globular-entangled-everything','OK','2020-05-01T12:00:00.000000+02:00');
+INSERT INTO queue VALUES(2,'url','https://ektoplazm.com/free-music/white-morph-dream-catcher',NULL,NULL);
+INSERT INTO blacklist VALUES(1,'artist','donald duck');
+INSERT INTO blacklist VALUES(2,'label','sony');